@@ -1,10 +1,14 @@
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use directories::UserDirs;
 use ratatui::widgets::ListState;
-use utils::parse_job;
+use utils::ipc::{self, Command, Reply};
+use utils::{
+    parse_job, read_output, update_jobs_file, HistoryFilter, Job, JobStats, JobsFile, RunStatus,
+};
 
-use crate::app::{App, Input};
+use crate::app::{App, Focus, Input, Notice, State, HISTORY_PAGE_SIZE};
 
 pub struct JobList<T> {
     pub state: ListState,
@@ -109,10 +113,15 @@ impl App {
         let job_input: String =
             self.name_input.input.clone() + ": " + self.cron_input.input.as_str();
         match parse_job(job_input) {
-            Some(new_job) => {
+            Some(mut new_job) => {
+                // New jobs go to the main jobs file
+                let source = self.config.jobs_file.clone();
+                new_job.source = Some(source.clone());
                 self.jobs.items.push(new_job);
                 self.jobs.state.select(Some(self.jobs.items.len() - 1));
-                self.write_jobs();
+                self.write_jobs(&source);
+                self.history_page = 0;
+                self.load_history();
                 Some(())
             }
             None => None,
@@ -122,10 +131,12 @@ impl App {
         let job_input: String =
             self.name_input.input.clone() + ": " + self.cron_input.input.as_str();
         match parse_job(job_input) {
-            Some(modified_job) => {
+            Some(mut modified_job) => {
                 let index = self.jobs.state.selected().unwrap();
+                let source = self.source_of(&self.jobs.items[index]);
+                modified_job.source = Some(source.clone());
                 self.jobs.items[index] = modified_job;
-                self.write_jobs();
+                self.write_jobs(&source);
                 Some(())
             }
             None => None,
@@ -133,27 +144,350 @@ impl App {
     }
     pub fn delete_job(&mut self) {
         if let Some(index) = self.jobs.state.selected() {
-            self.jobs.items.remove(index);
+            let removed = self.jobs.items.remove(index);
+            let source = self.source_of(&removed);
             if index > 0 {
                 self.jobs.state.select(Some(index - 1));
             } else {
                 self.jobs.state.select(None);
             }
-            self.write_jobs();
+            self.write_jobs(&source);
+            self.history_page = 0;
+            self.load_history();
         }
     }
-    fn write_jobs(&self) {
-        let jobs_file = UserDirs::new()
-            .unwrap()
-            .home_dir()
-            .join("job_scheduler")
-            .join("jobs");
-        let content: Vec<String> = self
-            .jobs
-            .items
+    // Disabled jobs keep their schedule but the daemon doesn't run them. A running daemon saves
+    // the change and applies it at once, without it the file is written here.
+    pub fn toggle_job(&mut self) {
+        let Some(index) = self.jobs.state.selected() else {
+            return;
+        };
+        let job = &mut self.jobs.items[index];
+        job.enabled = !job.enabled;
+        let command = if job.enabled {
+            Command::Resume {
+                job: job.name.clone(),
+            }
+        } else {
+            Command::Pause {
+                job: job.name.clone(),
+            }
+        };
+        let saved = match ipc::send(&self.config.socket(), command) {
+            Ok(Reply::Error { message }) => {
+                self.notice = Some(Notice::Error(format!("Not saved, {}", message)));
+                false
+            }
+            Ok(_) => true,
+            Err(_) => {
+                let source = self.source_of(&self.jobs.items[index]);
+                self.write_jobs(&source)
+            }
+        };
+        if !saved {
+            let job = &mut self.jobs.items[index];
+            job.enabled = !job.enabled;
+        }
+    }
+    pub fn toggle_scheduler(&mut self) {
+        let command = match self.scheduler_paused {
+            Some(true) => Command::ResumeScheduler,
+            _ => Command::PauseScheduler,
+        };
+        self.notice = match ipc::send(&self.config.socket(), command) {
+            Ok(Reply::Error { message }) => Some(Notice::Error(message)),
+            Ok(_) => None,
+            Err(e) => Some(Notice::Error(e)),
+        };
+        self.refresh_scheduler_state();
+    }
+    // Ask the daemon to start the selected job now, `args` replacing its arguments unless
+    // there are none. Returns `None` when the arguments can't be split into words.
+    pub fn run_job_now(&mut self, args: Option<&str>) -> Option<()> {
+        let job = self.get_selected_job()?;
+        let args = match args.map(shell_words::split) {
+            Some(Ok(args)) if args.is_empty() => None,
+            Some(Ok(args)) => Some(args),
+            Some(Err(e)) => {
+                self.notice = Some(Notice::Error(format!("Invalid arguments, {}", e)));
+                return None;
+            }
+            None => None,
+        };
+        let command = Command::RunNow {
+            job: job.name.clone(),
+            args,
+        };
+        self.notice = Some(match ipc::send(&self.config.socket(), command) {
+            Ok(Reply::Started { run_id }) => {
+                Notice::Info(format!("Started {} (run {})", job.name, run_id))
+            }
+            Ok(Reply::Error { message }) => Notice::Error(message),
+            Ok(_) => Notice::Error("Unexpected answer from the daemon".to_string()),
+            Err(e) => Notice::Error(e),
+        });
+        Some(())
+    }
+    pub fn refresh_scheduler_state(&mut self) {
+        self.scheduler_paused = match ipc::send(&self.config.socket(), Command::Status) {
+            Ok(Reply::Status(status)) => Some(status.paused),
+            _ => None,
+        };
+    }
+    // Load the current page of the selected job runs, the page is kept in range
+    pub fn load_history(&mut self) {
+        self.history.clear();
+        self.stats = JobStats::default();
+        self.history_state
+            .select(self.history_state.selected().map(|_| 0));
+        let (Some(store), Some(job)) = (&self.history_store, self.get_selected_job()) else {
+            self.history_pages = 0;
+            return;
+        };
+        let filter = HistoryFilter {
+            name: Some(job.name.clone()),
+            status: self.status_filter,
+        };
+        let loaded = store.count(&filter).and_then(|count| {
+            self.history_pages = count.div_ceil(HISTORY_PAGE_SIZE);
+            self.history_page = self.history_page.min(self.history_pages.saturating_sub(1));
+            let offset = self.history_page * HISTORY_PAGE_SIZE;
+            let page = store.query(&filter, offset, HISTORY_PAGE_SIZE)?;
+            Ok((page, store.stats(&job.name)?))
+        });
+        match loaded {
+            Ok((history, stats)) => {
+                self.history = history;
+                self.stats = stats;
+            }
+            Err(e) => {
+                self.notice = Some(Notice::Error(format!("Couldn't read the history: {}", e)))
+            }
+        }
+    }
+    // Another job is selected, its history starts from the most recent runs
+    pub fn select_job(&mut self, next: bool) {
+        match next {
+            true => self.jobs.next(),
+            false => self.jobs.previous(),
+        }
+        self.history_page = 0;
+        self.load_history();
+    }
+    pub fn next_history_page(&mut self) {
+        if self.history_page + 1 < self.history_pages {
+            self.history_page += 1;
+            self.load_history();
+        }
+    }
+    pub fn previous_history_page(&mut self) {
+        if self.history_page > 0 {
+            self.history_page -= 1;
+            self.load_history();
+        }
+    }
+    // All runs, then only the successful ones, the failed ones and so on
+    pub fn cycle_status_filter(&mut self) {
+        self.status_filter = match self.status_filter {
+            None => Some(RunStatus::Success),
+            Some(RunStatus::Success) => Some(RunStatus::Error),
+            Some(RunStatus::Error) => Some(RunStatus::Killed),
+            Some(RunStatus::Killed) => Some(RunStatus::Timeout),
+            Some(RunStatus::Timeout) => None,
+        };
+        self.history_page = 0;
+        self.load_history();
+    }
+    pub fn next_history(&mut self) {
+        let count = self.get_selected_job_history().len();
+        if count == 0 {
+            return;
+        }
+        let i = match self.history_state.selected() {
+            Some(i) if i + 1 < count => i + 1,
+            _ => 0,
+        };
+        self.history_state.select(Some(i));
+    }
+    pub fn previous_history(&mut self) {
+        let count = self.get_selected_job_history().len();
+        if count == 0 {
+            return;
+        }
+        let i = match self.history_state.selected() {
+            Some(i) if i > 0 && i < count => i - 1,
+            _ => count - 1,
+        };
+        self.history_state.select(Some(i));
+    }
+    pub fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Jobs => {
+                self.history_state.select(Some(0));
+                Focus::History
+            }
+            Focus::History => {
+                self.history_state.select(None);
+                Focus::Jobs
+            }
+        };
+    }
+    // Load the full output of the selected run, or of the last one if none is selected
+    pub fn open_output(&mut self) {
+        let index = self.history_state.selected().unwrap_or(0);
+        let output = self
+            .get_selected_job_history()
+            .get(index)
+            .and_then(|statement| statement.output.clone());
+        self.output.content = match output {
+            Some(path) => match read_output(&path) {
+                Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+                Err(e) => format!("Unable to read {}: {}", path.display(), e),
+            },
+            None => "No output was saved for this run".to_string(),
+        };
+        self.output.scroll = 0;
+        self.current_state = State::ViewingOutput;
+    }
+    pub fn source_of(&self, job: &Job) -> PathBuf {
+        job.source
+            .clone()
+            .unwrap_or_else(|| self.config.jobs_file.clone())
+    }
+    // Only the lines of the jobs that changed in the file `source` are rewritten, with its own
+    // defaults and format. The daemon picks the changes up by itself. A file left out when
+    // loading isn't written, what couldn't be read would be lost. Returns whether it was saved.
+    fn write_jobs(&mut self, source: &Path) -> bool {
+        let (defaults, format) = match self
+            .jobs_files
+            .files
             .iter()
-            .map(|el| format!("{}: {} {}", el.name, el.cron, el.command))
-            .collect();
-        fs::write(jobs_file, content.join("\n")).unwrap();
+            .find(|(path, _)| path == source)
+        {
+            Some((_, jobs_file)) => (jobs_file.defaults.clone(), jobs_file.format),
+            None => {
+                self.notice = Some(Notice::Error(format!(
+                    "Not saved, {} couldn't be loaded",
+                    source.display()
+                )));
+                return false;
+            }
+        };
+        let content = JobsFile {
+            defaults,
+            jobs: self
+                .jobs
+                .items
+                .iter()
+                .filter(|job| self.source_of(job) == source)
+                .cloned()
+                .collect(),
+            format,
+        };
+        // Rebuilding a file that couldn't be read would lose its comments and blank lines
+        let original = match fs::read_to_string(source) {
+            Ok(original) => Ok(original),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && source == self.config.jobs_file => {
+                Ok(String::new())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let written = original
+            .and_then(|original| update_jobs_file(&original, &content))
+            .and_then(|updated| fs::write(source, updated).map_err(|e| e.to_string()));
+        if let Err(e) = &written {
+            self.notice = Some(Notice::Error(format!(
+                "Not saved, {}: {}",
+                source.display(),
+                e
+            )));
+        }
+        written.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::{Config, JobsFiles};
+
+    // A client on jobs files of its own home folder, `jobs` being the main one, with no daemon
+    // running
+    fn client(test: &str, files: &[(&str, &str)]) -> (PathBuf, App) {
+        let home = std::env::temp_dir().join(format!("client-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        let config = Config::parse("", &home).unwrap();
+        fs::create_dir_all(&config.jobs_dir).unwrap();
+        for (name, content) in files {
+            let path = match *name {
+                "jobs" => config.jobs_file.clone(),
+                name => config.jobs_dir.join(name),
+            };
+            fs::write(path, content).unwrap();
+        }
+        let (jobs_files, errors) = JobsFiles::load(&config.jobs_file, &config.jobs_dir);
+        assert!(errors.is_empty());
+        (home, App::new(config, None, jobs_files))
+    }
+
+    fn toggle(app: &mut App, name: &str) -> bool {
+        let index = app.jobs.items.iter().position(|job| job.name == name);
+        app.jobs.state.select(index);
+        app.notice = None;
+        app.toggle_job();
+        app.jobs.items[index.unwrap()].enabled
+    }
+
+    fn not_saved(app: &App) -> bool {
+        matches!(&app.notice, Some(Notice::Error(message)) if message.starts_with("Not saved"))
+    }
+
+    #[test]
+    fn saved_toggle() {
+        let (home, mut app) = client(
+            "saved",
+            &[("jobs", "# nightly\nbackup: 0 2 * * * backup.sh\n")],
+        );
+        assert!(!toggle(&mut app, "backup"));
+        assert!(app.notice.is_none());
+        assert_eq!(
+            fs::read_to_string(&app.config.jobs_file).unwrap(),
+            "# nightly\nbackup [enabled=false]: 0 2 * * * backup.sh\n"
+        );
+        fs::remove_dir_all(&home).unwrap();
+    }
+    #[test]
+    fn unsaved_toggles() {
+        let yaml = "# weekly\njob:\n- name: report\n  cron: 0 3 * * 1\n  command: report.sh\n";
+        let (home, mut app) = client(
+            "unsaved",
+            &[
+                ("jobs", "backup: 0 2 * * * backup.sh\n"),
+                ("report.yaml", yaml),
+                ("sync", "sync: */5 * * * * sync.sh\n"),
+            ],
+        );
+        // Its comments would be lost
+        assert!(toggle(&mut app, "report"));
+        assert!(not_saved(&app));
+        let report = app.config.jobs_dir.join("report.yaml");
+        assert_eq!(fs::read_to_string(report).unwrap(), yaml);
+
+        // What can't be read isn't rebuilt from nothing
+        let sync = app.config.jobs_dir.join("sync");
+        let latin1 = b"sync: */5 * * * * sync.sh\n# caf\xe9\n";
+        fs::write(&sync, latin1).unwrap();
+        assert!(toggle(&mut app, "sync"));
+        assert!(not_saved(&app));
+        assert_eq!(fs::read(&sync).unwrap(), latin1);
+
+        // Nor written where it can't be
+        fs::remove_dir_all(&home).unwrap();
+        assert!(toggle(&mut app, "backup"));
+        assert!(not_saved(&app));
+        app.name_input.input = "clean".to_string();
+        app.cron_input.input = "0 4 * * * clean.sh".to_string();
+        assert_eq!(app.append_job(), Some(()));
+        assert!(not_saved(&app) && !app.config.jobs_file.exists());
     }
 }
@@ -4,9 +4,12 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use ratatui::{prelude::*, Terminal};
+use ratatui::{prelude::*, widgets::ListState, Terminal};
 use std::{error::Error, io};
-use utils::{History, Job};
+use utils::{Config, History, HistoryRecord, HistoryStore, Job, JobStats, JobsFiles, RunStatus};
+
+// Runs loaded at once in the history list
+pub const HISTORY_PAGE_SIZE: usize = 50;
 
 #[derive(PartialEq)]
 pub enum InputMode {
@@ -32,20 +35,81 @@ pub enum State {
     EditingJob,
     AddingJob(InputSwitch),
     DeletingJob,
+    ViewingOutput,
+    // Typing the arguments of a manual run
+    EnteringArgs,
+}
+
+#[derive(PartialEq)]
+pub enum Focus {
+    Jobs,
+    History,
+}
+
+pub enum Notice {
+    Info(String),
+    Error(String),
 }
+
+pub struct Output {
+    pub content: String,
+    pub scroll: u16,
+}
+
 pub struct App {
     pub jobs: JobList<Job>,
+    // Defaults and format of each file the jobs come from, its jobs are moved to `jobs`
+    pub jobs_files: JobsFiles,
+    // Where the jobs file, the history and the daemon socket are
+    pub config: Config,
+    pub history_store: Option<Box<dyn HistoryStore + Send>>,
+    // Page of the runs of the selected job, `history_page` counting from the most recent
     pub history: History,
+    pub history_page: usize,
+    pub history_pages: usize,
+    // Only the runs ending this way are listed
+    pub status_filter: Option<RunStatus>,
+    pub history_state: ListState,
+    // Over all the runs of the selected job, whatever the filter
+    pub stats: JobStats,
+    pub focus: Focus,
+    pub output: Output,
     pub name_input: Input,
     pub cron_input: Input,
     pub current_state: State,
+    // Whether the daemon scheduler is paused, `None` while it can't be reached
+    pub scheduler_paused: Option<bool>,
+    // Outcome of the last command sent to the daemon
+    pub notice: Option<Notice>,
 }
 
 impl App {
-    fn new(history: History, jobs: Vec<Job>) -> App {
+    pub fn new(
+        config: Config,
+        history_store: Option<Box<dyn HistoryStore + Send>>,
+        mut jobs_files: JobsFiles,
+    ) -> App {
+        let jobs = jobs_files
+            .files
+            .iter_mut()
+            .flat_map(|(_, jobs_file)| std::mem::take(&mut jobs_file.jobs))
+            .collect();
         App {
             jobs: JobList::with_items(jobs),
-            history,
+            jobs_files,
+            config,
+            history_store,
+            history: vec![],
+            history_page: 0,
+            history_pages: 0,
+            status_filter: None,
+            history_state: ListState::default(),
+            stats: JobStats::default(),
+            focus: Focus::Jobs,
+            output: Output {
+                content: String::new(),
+                scroll: 0,
+            },
             name_input: Input {
                 input: String::new(),
                 cursor_position: 0,
@@ -57,10 +121,12 @@ impl App {
                 input_mode: InputMode::Normal,
             },
             current_state: State::NotEditing,
+            scheduler_paused: None,
+            notice: None,
         }
     }
     pub fn get_selected_job(&self) -> Option<Job> {
-        if self.jobs.items.len() == 0 {
+        if self.jobs.items.is_empty() {
             return None;
         }
         self.jobs
@@ -68,15 +134,28 @@ impl App {
             .selected()
             .map(|index| self.jobs.items[index].clone())
     }
+    // Runs of the selected job on the current page, most recent first
+    pub fn get_selected_job_history(&self) -> Vec<&HistoryRecord> {
+        self.history.iter().collect()
+    }
     pub fn get_selected_job_as_strings(&self) -> (String, String) {
         match self.get_selected_job() {
-            Some(job) => (job.name.to_owned(), job.cron + " " + &job.command),
+            Some(job) if job.settings().is_empty() => (job.name, job.cron + " " + &job.command),
+            Some(job) => (
+                format!("{} [{}]", job.name, job.settings()),
+                job.cron + " " + &job.command,
+            ),
             None => (String::new(), String::new()),
         }
     }
 }
 
-pub fn run(history: History, jobs: Vec<Job>) -> Result<(), Box<dyn Error>> {
+pub fn run(
+    config: Config,
+    history_store: Option<Box<dyn HistoryStore + Send>>,
+    jobs_files: JobsFiles,
+    notice: Option<Notice>,
+) -> Result<(), Box<dyn Error>> {
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -85,7 +164,10 @@ pub fn run(history: History, jobs: Vec<Job>) -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app and run it
-    let app = App::new(history, jobs);
+    let mut app = App::new(config, history_store, jobs_files);
+    app.load_history();
+    app.notice = notice;
+    app.refresh_scheduler_state();
     let res = event_loop(&mut terminal, app);
 
     // restore terminal
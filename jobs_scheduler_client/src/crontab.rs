@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use utils::{export_crontab, import_crontab, Config, JobsFiles};
+
+pub const USAGE: &str =
+    "Usage: jobs_scheduler_client [--config FILE] [import [--system] [FILE] | export [--system]]
+    --config  the configuration file the daemon was started with
+    import  adds the jobs of FILE, or of `crontab -l`, as a new file of the jobs directory
+    export  writes every job as crontab lines, to install with `crontab -`
+    --system  the crontab has a user column, like /etc/crontab and the files of /etc/cron.d";
+
+// The file is named after the crontab and refused if it exists, it's removed again when the
+// jobs it adds don't fit with the others
+pub fn import(config: &Config, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (flags, files): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|arg| arg.starts_with("--"));
+    if flags.iter().any(|flag| *flag != "--system") || files.len() > 1 {
+        return Err(USAGE.into());
+    }
+    let file = files.first().map(|file| Path::new(file.as_str()));
+    let system = !flags.is_empty()
+        || file.is_some_and(|file| {
+            file.starts_with("/etc/cron.d") || file == Path::new("/etc/crontab")
+        });
+    let content = match file {
+        Some(file) => fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?,
+        None => {
+            let output = Command::new("crontab").arg("-l").output()?;
+            if !output.status.success() {
+                let error = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("crontab -l failed: {}", error.trim()).into());
+            }
+            String::from_utf8(output.stdout)?
+        }
+    };
+    let jobs_files = load(config);
+    let taken: Vec<String> = jobs_files
+        .files
+        .iter()
+        .flat_map(|(_, jobs_file)| jobs_file.jobs.iter().map(|job| job.name.clone()))
+        .collect();
+    let name = file
+        .and_then(|file| file.file_stem())
+        .and_then(|name| name.to_str())
+        .unwrap_or("crontab");
+    let imported = import_crontab(&content, system, &taken).map_err(|e| match file {
+        Some(file) => format!("{}: {}", file.display(), e),
+        None => format!("crontab -l: {}", e),
+    })?;
+    let target = config.jobs_dir.join(name);
+    if target.exists() {
+        return Err(format!("{} already exists", target.display()).into());
+    }
+    fs::create_dir_all(&config.jobs_dir)?;
+    fs::write(&target, imported.to_string())?;
+    // `after` may name a job that isn't there
+    let (_, errors) = JobsFiles::load(&config.jobs_file, &config.jobs_dir);
+    if let Some(e) = errors.into_iter().find(|e| e.path == target) {
+        let _ = fs::remove_file(&target);
+        return Err(e.into());
+    }
+    println!(
+        "Imported {} jobs into {}",
+        imported.jobs.len(),
+        target.display()
+    );
+    Ok(())
+}
+
+// The jobs of each file follow a comment naming it
+pub fn export(config: &Config, args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.iter().any(|arg| arg != "--system") {
+        return Err(USAGE.into());
+    }
+    let system = !args.is_empty();
+    let jobs_files = load(config);
+    for (path, jobs_file) in &jobs_files.files {
+        if jobs_file.jobs.is_empty() {
+            continue;
+        }
+        print!(
+            "# From {}\n{}",
+            path.display(),
+            export_crontab(jobs_file, system)
+        );
+    }
+    Ok(())
+}
+
+// The files that can't be used are left out with a warning
+fn load(config: &Config) -> JobsFiles {
+    let (jobs_files, errors) = JobsFiles::load(&config.jobs_file, &config.jobs_dir);
+    for e in errors {
+        eprintln!("Left out {}", e);
+    }
+    jobs_files
+}
@@ -1,5 +1,5 @@
 use crate::{
-    app::{App, Input, InputMode, InputSwitch, State},
+    app::{App, Focus, Input, InputMode, InputSwitch, State},
     ui::draw,
 };
 use crossterm::event::{self, Event, KeyCode, KeyEventKind};
@@ -14,8 +14,15 @@ pub fn event_loop<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::R
                 match app.current_state {
                     State::NotEditing => match key.code {
                         KeyCode::Esc => return Ok(()),
-                        KeyCode::Down => app.jobs.next(),
-                        KeyCode::Up => app.jobs.previous(),
+                        KeyCode::Down if app.focus == Focus::History => app.next_history(),
+                        KeyCode::Up if app.focus == Focus::History => app.previous_history(),
+                        KeyCode::Down => app.select_job(true),
+                        KeyCode::Up => app.select_job(false),
+                        KeyCode::PageDown => app.next_history_page(),
+                        KeyCode::PageUp => app.previous_history_page(),
+                        KeyCode::Char('f') => app.cycle_status_filter(),
+                        KeyCode::Tab => app.toggle_focus(),
+                        KeyCode::Char('o') => app.open_output(),
                         KeyCode::Enter => {
                             app.cron_input.input_mode = InputMode::Editing;
                             app.current_state = State::EditingJob;
@@ -28,6 +35,17 @@ pub fn event_loop<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::R
                             app.cron_input.input = String::new();
                         }
                         KeyCode::Char('d') => app.current_state = State::DeletingJob,
+                        KeyCode::Char('r') => {
+                            app.run_job_now(None);
+                        }
+                        KeyCode::Char('R') if app.get_selected_job().is_some() => {
+                            app.cron_input.input_mode = InputMode::Editing;
+                            app.cron_input.input = String::new();
+                            app.cron_input.reset_cursor();
+                            app.current_state = State::EnteringArgs;
+                        }
+                        KeyCode::Char('p') => app.toggle_job(),
+                        KeyCode::Char('P') => app.toggle_scheduler(),
                         _ => {}
                     },
                     State::EditingJob => match key.code {
@@ -97,6 +115,40 @@ pub fn event_loop<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::R
                             _ => {}
                         }
                     }
+                    State::EnteringArgs => match key.code {
+                        KeyCode::Enter => {
+                            let args = app.cron_input.input.clone();
+                            match app.run_job_now(Some(&args)) {
+                                Some(_) => {
+                                    app.cron_input.input_mode = InputMode::Normal;
+                                    app.cron_input.reset_cursor();
+                                    app.current_state = State::NotEditing;
+                                }
+                                None => app.cron_input.input_mode = InputMode::Error,
+                            }
+                        }
+                        KeyCode::Char(to_insert) => app.cron_input.enter_char(to_insert),
+                        KeyCode::Backspace => app.cron_input.backspace_char(),
+                        KeyCode::Delete => app.cron_input.delete_char(),
+                        KeyCode::Left => app.cron_input.move_cursor_left(),
+                        KeyCode::Right => app.cron_input.move_cursor_right(),
+                        KeyCode::Esc => {
+                            app.cron_input.input_mode = InputMode::Normal;
+                            app.cron_input.reset_cursor();
+                            app.current_state = State::NotEditing;
+                        }
+                        _ => {}
+                    },
+                    State::ViewingOutput => match key.code {
+                        KeyCode::Down => app.output.scroll = app.output.scroll.saturating_add(1),
+                        KeyCode::Up => app.output.scroll = app.output.scroll.saturating_sub(1),
+                        KeyCode::PageDown => {
+                            app.output.scroll = app.output.scroll.saturating_add(20)
+                        }
+                        KeyCode::PageUp => app.output.scroll = app.output.scroll.saturating_sub(20),
+                        KeyCode::Esc => app.current_state = State::NotEditing,
+                        _ => {}
+                    },
                     State::DeletingJob => match key.code {
                         KeyCode::Char('y') => {
                             app.delete_job();
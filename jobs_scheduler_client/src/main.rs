@@ -1,30 +1,66 @@
-use directories::UserDirs;
-use std::{error::Error, fs};
-use utils::{parse_history, parse_jobs};
+use app::Notice;
+use std::{env, error::Error, path::PathBuf, process};
+use utils::{Config, JobsFiles};
 
 mod actions;
 mod app;
+mod crontab;
 mod events;
 mod ui;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let js_dir = UserDirs::new().unwrap().home_dir().join("job_scheduler");
-    let history_file = js_dir.join("history");
-    let jobs_file = js_dir.join("jobs");
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // Same file as the daemon, for the paths, so the one it was started with too
+    let config_file = match args.first().map(String::as_str) {
+        Some("--config") if args.len() > 1 => {
+            let path = args.remove(1);
+            args.remove(0);
+            Some(PathBuf::from(path))
+        }
+        Some(arg) if arg.starts_with("--config=") => {
+            Some(PathBuf::from(&args.remove(0)["--config=".len()..]))
+        }
+        _ => None,
+    };
+    let config = match Config::load(config_file.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration, {}", e);
+            process::exit(2);
+        }
+    };
 
-    // get saved jobs
-    let jobs = match fs::read_to_string(jobs_file) {
-        Ok(jobs_content) => parse_jobs(jobs_content),
-        Err(_) => {
-            println!("Unable to read the jobs file");
-            vec![]
+    // `import` and `export` move jobs from and to crontabs, the TUI starts otherwise
+    let command = match args.first().map(String::as_str) {
+        Some("import") => crontab::import,
+        Some("export") => crontab::export,
+        Some(_) => {
+            eprintln!("{}", crontab::USAGE);
+            process::exit(2);
         }
+        None => return run(config),
     };
+    if let Err(e) = command(&config, &args[1..]) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    // get saved jobs, from the jobs file and the files of the jobs directory
+    let (jobs_files, errors) = JobsFiles::load(&config.jobs_file, &config.jobs_dir);
+    let mut notices: Vec<String> = errors.iter().map(|e| format!("Left out {}", e)).collect();
 
-    // get jobs history
-    let history_content =
-        fs::read_to_string(history_file).expect("Should have been able to read the history file");
-    let history = parse_history(history_content);
+    // get jobs history, the daemon writes it
+    let history_store = match config.history_backend.open(&config.history_file) {
+        Ok(history_store) => Some(history_store),
+        Err(e) => {
+            notices.push(format!("No history, {}", e));
+            None
+        }
+    };
+    let notice = (!notices.is_empty()).then(|| Notice::Error(notices.join("; ")));
 
-    app::run(history, jobs)
+    app::run(config, history_store, jobs_files, notice)
 }
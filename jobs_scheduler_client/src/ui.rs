@@ -1,4 +1,5 @@
-use crate::app::{App, InputMode, InputSwitch, State};
+use crate::app::{App, InputMode, InputSwitch, Notice, State};
+use chrono::{DateTime, Duration, Utc};
 use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Wrap};
 use ratatui::{
     prelude::*,
@@ -6,6 +7,7 @@ use ratatui::{
     text::{Line, Span},
     Frame,
 };
+use utils::{RunStatus, Trigger};
 
 pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     // Create main block for title
@@ -34,12 +36,24 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     // Create two chunks for side by side lists
     let lists_chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(30), Constraint::Percentage(70)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(25),
+                Constraint::Percentage(50),
+                Constraint::Percentage(25),
+            ]
+            .as_ref(),
+        )
         .split(core_chunk);
 
     // Draw differents parts of the app
     draw_job_list(f, app, lists_chunks[0]);
-    draw_history_list(f, app, lists_chunks[1]);
+    if app.current_state == State::ViewingOutput {
+        draw_output(f, app, lists_chunks[1]);
+    } else {
+        draw_history_list(f, app, lists_chunks[1]);
+    }
+    draw_stats(f, app, lists_chunks[2]);
     draw_editor(f, app, editor_chunk);
     draw_footer(f, app, footer_chunk);
 }
@@ -53,15 +67,21 @@ where
         .jobs
         .items
         .iter()
-        .map(|i| {
-            let lines = vec![Line::from(i.name.clone())];
-            ListItem::new(lines).style(Style::default().fg(Color::White))
+        .map(|i| match i.enabled {
+            true => ListItem::new(vec![Line::from(i.name.clone())])
+                .style(Style::default().fg(Color::White)),
+            false => ListItem::new(vec![Line::from(format!("{} (paused)", i.name))])
+                .style(Style::default().fg(Color::DarkGray)),
         })
         .collect();
+    let title = match app.scheduler_paused {
+        Some(true) => "Jobs (all paused)",
+        _ => "Jobs",
+    };
 
     // Create a List from all list items and highlight the currently selected one
     let items = List::new(items)
-        .block(Block::default().borders(Borders::ALL).title("Jobs"))
+        .block(Block::default().borders(Borders::ALL).title(title))
         .highlight_style(
             Style::default()
                 .bg(Color::LightGreen)
@@ -79,42 +99,118 @@ where
 {
     // The event list doesn't have any state and only displays the current state of the list.
     let events: Vec<ListItem> = app
-        .history
-        .iter()
-        .rev()
-        .filter(|history_line| {
-            if let Some(job) = app.get_selected_job() {
-                history_line.name == job.name
-            } else {
-                false
-            }
-        })
+        .get_selected_job_history()
+        .into_iter()
         .map(|history_statement| {
-            let status = &history_statement.status;
+            let status = history_statement.status;
             // Colorcode the level depending on its type
-            let s = match status.as_str() {
-                "SUCCESS" => Style::default().fg(Color::Blue),
-                "ERROR" => Style::default().fg(Color::Red),
-                _ => Style::default(),
+            let s = match status {
+                RunStatus::Success => Style::default().fg(Color::Blue),
+                RunStatus::Error => Style::default().fg(Color::Red),
+                RunStatus::Killed | RunStatus::Timeout => Style::default().fg(Color::Magenta),
             };
             let line = Line::from(vec![
-                Span::styled(format!("{status:<9}"), s),
+                Span::styled(format!("{:<9}", status.to_string()), s),
                 " ".into(),
                 Span::styled(
-                    history_statement.timestamp.to_rfc2822(),
+                    history_statement.started.to_rfc2822(),
                     Style::default().italic(),
                 ),
                 "  ".into(),
-                history_statement.error_message.clone().into(),
+                match history_statement.trigger {
+                    Trigger::Schedule => "".into(),
+                    Trigger::CatchUp => Span::styled("(caught up) ", Style::default().italic()),
+                    Trigger::Manual => Span::styled("(manual) ", Style::default().italic()),
+                    Trigger::Dependency => Span::styled("(dependency) ", Style::default().italic()),
+                },
+                history_statement.message.clone().into(),
             ]);
 
             ListItem::new(vec![line])
         })
         .collect();
+    let mut title = match app.history_pages {
+        0 | 1 => "History".to_string(),
+        pages => format!("History - page {}/{}", app.history_page + 1, pages),
+    };
+    if let Some(status) = app.status_filter {
+        title += &format!(" - {} only", status);
+    }
     let events_list = List::new(events)
-        .block(Block::default().borders(Borders::ALL).title("History"))
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
         .start_corner(Corner::TopLeft);
-    f.render_widget(events_list, area);
+    f.render_stateful_widget(events_list, area, &mut app.history_state);
+}
+fn draw_stats<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let stats = &app.stats;
+    let or_none = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let time = |time: Option<DateTime<Utc>>| {
+        or_none(time.map(|time| time.format("%Y-%m-%d %H:%M").to_string()))
+    };
+    let duration = |duration: Option<Duration>| or_none(duration.map(format_elapsed));
+    let streak_style = match stats.failure_streak {
+        0 => Style::default(),
+        _ => Style::default().fg(Color::Red),
+    };
+    // Where the job is saved, from the data folder
+    let selected = app.jobs.state.selected();
+    let file = selected
+        .and_then(|index| app.jobs.items.get(index))
+        .map(|job| {
+            let source = app.source_of(job);
+            let source = source.strip_prefix(&app.config.data_dir).unwrap_or(&source);
+            source.display().to_string()
+        });
+    let lines = vec![
+        Line::from(format!("File: {}", or_none(file))),
+        Line::from(format!("Runs: {}", stats.runs)),
+        Line::from(format!(
+            "Success rate: {}",
+            or_none(
+                stats
+                    .success_rate
+                    .map(|rate| format!("{:.1}%", rate * 100.0))
+            )
+        )),
+        Line::from(format!(
+            "Median duration: {}",
+            duration(stats.median_duration)
+        )),
+        Line::from(format!("95th percentile: {}", duration(stats.p95_duration))),
+        Line::from(format!("Longest: {}", duration(stats.max_duration))),
+        Line::from(format!("Last success: {}", time(stats.last_success))),
+        Line::from(format!("Last failure: {}", time(stats.last_failure))),
+        Line::from(Span::styled(
+            format!("Failures in a row: {}", stats.failure_streak),
+            streak_style,
+        )),
+        Line::from(format!("Average delay: {}", duration(stats.average_delay))),
+    ];
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title("Stats"))
+        .wrap(Wrap { trim: false });
+    f.render_widget(paragraph, area);
+}
+// Milliseconds are only worth showing for short runs
+fn format_elapsed(duration: Duration) -> String {
+    let millis = duration.num_milliseconds().max(0);
+    match millis {
+        0..=59_999 => format!("{:.1}s", millis as f64 / 1000.0),
+        _ => format!("{}m{:02}s", millis / 60_000, millis / 1000 % 60),
+    }
+}
+fn draw_output<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let output = Paragraph::new(app.output.content.as_str())
+        .block(Block::default().borders(Borders::ALL).title("Output"))
+        .scroll((app.output.scroll, 0));
+    f.render_widget(output, area);
 }
 fn draw_editor<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
@@ -150,7 +246,10 @@ where
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title("Cron & Command"),
+                .title(match app.current_state {
+                    State::EnteringArgs => "Arguments",
+                    _ => "Cron & Command",
+                }),
         );
 
     // Render widgets
@@ -159,7 +258,7 @@ where
 
     // Make the cursor visible
     match app.current_state {
-        State::EditingJob => f.set_cursor(
+        State::EditingJob | State::EnteringArgs => f.set_cursor(
             input_chunks[1].x + app.cron_input.cursor_position as u16 + 1,
             input_chunks[1].y + 1,
         ),
@@ -190,13 +289,39 @@ where
                 Span::from("Add: a"),
                 Span::raw("  "),
                 Span::from("Delete: d"),
+                Span::raw("  "),
+                Span::from("Run now: r"),
+                Span::raw("  "),
+                Span::from("Run with arguments: R"),
+                Span::raw("  "),
+                Span::from("Pause/resume job: p"),
+                Span::raw("  "),
+                Span::from("Pause/resume all: P"),
             ]),
             text::Line::from(vec![
                 Span::from("Move: Up/Down Arrows"),
                 Span::raw("  "),
+                Span::from("Switch to jobs/history: Tab"),
+                Span::raw("  "),
+                Span::from("Open output: o"),
+                Span::raw("  "),
+                Span::from("History pages: Page Up/Down"),
+                Span::raw("  "),
+                Span::from("Filter by status: f"),
+                Span::raw("  "),
                 Span::from("Quit: Escape"),
             ]),
         ],
+        State::ViewingOutput => vec![text::Line::from(vec![
+            Span::from("Scroll: Up/Down Arrows, Page Up/Down"),
+            Span::raw("  "),
+            Span::from("Close: Escape"),
+        ])],
+        State::EnteringArgs => vec![text::Line::from(vec![
+            Span::from("Run: Enter"),
+            Span::raw("  "),
+            Span::from("Cancel: Escape"),
+        ])],
         State::EditingJob => vec![text::Line::from(vec![
             Span::from("Validate: Enter"),
             Span::raw("  "),
@@ -222,9 +347,18 @@ where
             ]),
         ],
     };
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .title(Span::styled("Controls", Style::default()));
+    let title = match &app.notice {
+        Some(Notice::Info(notice)) => Span::styled(
+            format!("Controls - {}", notice),
+            Style::default().fg(Color::Green),
+        ),
+        Some(Notice::Error(notice)) => Span::styled(
+            format!("Controls - {}", notice),
+            Style::default().fg(Color::Red),
+        ),
+        None => Span::styled("Controls", Style::default()),
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
     let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: false });
     f.render_widget(paragraph, area);
 }
@@ -0,0 +1,94 @@
+use crate::logger::LogType;
+use std::path::PathBuf;
+use std::time::Duration;
+use utils::{parse_duration, parse_size, Retention};
+
+// Time runs in progress get to end on shutdown before being killed
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+const USAGE: &str =
+    "Usage: jobs_scheduler_daemon [--config FILE] [--drain-timeout DURATION] [--replace]
+    [--keep-for DURATION] [--keep-runs COUNT] [--keep-size SIZE] [--compress-after DURATION]
+    [--log-level debug|info|warn|error] [--log-stderr] [--log-max-size SIZE] [--log-keep COUNT]";
+
+// The settings also found in the config file win over it when given
+pub struct Args {
+    // Instead of config.toml in the XDG config directory
+    pub config: Option<PathBuf>,
+    pub drain_timeout: Duration,
+    // Shut the running daemon down instead of refusing to start
+    pub replace: bool,
+    pub retention: Retention,
+    pub log_level: Option<LogType>,
+    // Log to stderr rather than to the logs file, for supervisors that keep it
+    pub log_stderr: bool,
+    pub log_max_size: Option<u64>,
+    pub log_keep: Option<usize>,
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args {
+            config: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            replace: false,
+            retention: Retention::default(),
+            log_level: None,
+            log_stderr: false,
+            log_max_size: None,
+            log_keep: None,
+        };
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(format!("{} needs a value\n{}", flag, USAGE))
+            };
+            match flag.as_str() {
+                "--config" => parsed.config = Some(PathBuf::from(value()?)),
+                "--drain-timeout" => {
+                    let seconds = parse_duration("--drain-timeout", &value()?)?;
+                    parsed.drain_timeout = Duration::from_secs(seconds);
+                }
+                "--replace" => parsed.replace = true,
+                "--keep-for" => {
+                    parsed.retention.max_age = Some(parse_duration("--keep-for", &value()?)?)
+                }
+                "--keep-runs" => parsed.retention.max_runs = Some(parse_count(&flag, &value()?)?),
+                "--keep-size" => {
+                    parsed.retention.max_size = Some(parse_size("--keep-size", &value()?)?)
+                }
+                "--compress-after" => {
+                    let seconds = parse_duration("--compress-after", &value()?)?;
+                    parsed.retention.compress_after = Some(seconds);
+                }
+                "--log-level" => {
+                    let value = value()?;
+                    parsed.log_level = Some(LogType::parse(&value).ok_or(format!(
+                        "`--log-level` expects debug, info, warn or error, not `{}`",
+                        value
+                    ))?);
+                }
+                "--log-stderr" => parsed.log_stderr = true,
+                "--log-max-size" => {
+                    parsed.log_max_size = Some(parse_size("--log-max-size", &value()?)?)
+                }
+                "--log-keep" => parsed.log_keep = Some(parse_count(&flag, &value()?)?),
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ => return Err(format!("unknown argument `{}`\n{}", flag, USAGE)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse_count(flag: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` expects a number, not `{}`", flag, value))
+}
@@ -0,0 +1,176 @@
+use crate::scheduler::Event;
+use crate::{add_to_log, LogType};
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, prelude::*, BufReader};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use utils::ipc::{Reply, Request, Response, PROTOCOL_VERSION, SOCKET_NAME};
+
+// Accept clients on the control socket, their commands are handed to the scheduler
+pub fn listen(socket: &Path, events: Sender<Event>) -> io::Result<()> {
+    // Whoever can connect can run any job, so only the daemon's user can. The socket is bound in a
+    // directory no one else can enter and made private there before it's moved in place.
+    let mut private = socket.as_os_str().to_owned();
+    private.push(".new");
+    let private = PathBuf::from(private);
+    // Only one daemon holds the PID file lock, so what is left there is stale
+    match fs::remove_dir_all(&private) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join(SOCKET_NAME);
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, Permissions::from_mode(0o600))?;
+        fs::rename(&bound, socket)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&private);
+    let listener = listener?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let events = events.clone();
+                    thread::spawn(move || serve(stream, events));
+                }
+                Err(e) => add_to_log(
+                    LogType::Error,
+                    format!("Couldn't accept a control connection: {}", e),
+                ),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn serve(stream: UnixStream, events: Sender<Event>) {
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(request) if request.version != PROTOCOL_VERSION => Reply::Error {
+                message: format!(
+                    "Unsupported protocol version {}, the daemon speaks version {}",
+                    request.version, PROTOCOL_VERSION
+                ),
+            },
+            Ok(request) => {
+                let (reply_sender, reply_receiver) = channel();
+                if events
+                    .send(Event::Command(request.command, reply_sender))
+                    .is_err()
+                {
+                    return;
+                }
+                match reply_receiver.recv() {
+                    Ok(reply) => reply,
+                    // The scheduler stopped, the daemon is shutting down
+                    Err(_) => Reply::Error {
+                        message: "The daemon is shutting down".to_string(),
+                    },
+                }
+            }
+            Err(e) => Reply::Error {
+                message: format!("Invalid request: {}", e),
+            },
+        };
+        let Ok(mut answer) = serde_json::to_string(&Response::new(reply)) else {
+            return;
+        };
+        answer.push('\n');
+        if writer.write_all(answer.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+    use utils::ipc::Command;
+
+    // Responses to the request `lines` sent on one connection, with the scheduler answering each
+    // command it gets through `answer`, or stopping on `None`
+    fn exchange(lines: &[&str], answer: fn(Command) -> Option<Reply>) -> Vec<String> {
+        let (client, daemon) = UnixStream::pair().unwrap();
+        let (events, received) = channel();
+        thread::spawn(move || serve(daemon, events));
+        thread::spawn(move || {
+            for event in received {
+                if let Event::Command(command, reply) = event {
+                    if let Some(answer) = answer(command) {
+                        reply.send(answer).unwrap();
+                    }
+                }
+            }
+        });
+        (&client)
+            .write_all((lines.join("\n") + "\n").as_bytes())
+            .unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        BufReader::new(&client)
+            .lines()
+            .map(|line| line.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn control_requests() {
+        let responses = exchange(
+            &[
+                r#"{"version":1,"command":"run_now","job":"backup","args":["--full"]}"#,
+                "",
+                r#"{"version":1,"command":"cancel","run_id":"20240501-020000-000"}"#,
+                r#"{"version":1,"command":"pause","job":"nope"}"#,
+                r#"{"version":2,"command":"status"}"#,
+                r#"{"version":1,"command":"fly"}"#,
+                "not json",
+                r#"{"version":1,"command":"reload"}"#,
+            ],
+            |command| match command {
+                Command::RunNow { job, args } if job == "backup" => {
+                    assert_eq!(args, Some(vec!["--full".to_string()]));
+                    Some(Reply::Started {
+                        run_id: "20240501-020000-000".to_string(),
+                    })
+                }
+                Command::Cancel { run_id } if run_id == "20240501-020000-000" => Some(Reply::Done),
+                Command::Pause { job } => Some(Reply::Error {
+                    message: format!("No job named {}", job),
+                }),
+                // The scheduler stopped
+                Command::Reload => None,
+                command => panic!("unexpected {:?}", command),
+            },
+        );
+        assert_eq!(responses.len(), 7);
+        assert_eq!(
+            responses[..4],
+            [
+                r#"{"version":1,"result":"started","run_id":"20240501-020000-000"}"#,
+                r#"{"version":1,"result":"done"}"#,
+                r#"{"version":1,"result":"error","message":"No job named nope"}"#,
+                r#"{"version":1,"result":"error","message":"Unsupported protocol version 2, the daemon speaks version 1"}"#,
+            ]
+        );
+        assert!(responses[4].starts_with(
+            r#"{"version":1,"result":"error","message":"Invalid request: unknown variant `fly`"#
+        ));
+        assert!(responses[5]
+            .starts_with(r#"{"version":1,"result":"error","message":"Invalid request: expected"#));
+        assert_eq!(
+            responses[6],
+            r#"{"version":1,"result":"error","message":"The daemon is shutting down"}"#
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use utils::JobOptions;
+
+// The hard CPU limit comes a bit after the soft one so the job gets SIGXCPU before SIGKILL
+const CPU_GRACE_SECONDS: u64 = 5;
+const CPU_PERIOD_MICROSECONDS: u64 = 100_000;
+
+static DELEGATED_CGROUP: OnceLock<Result<PathBuf, String>> = OnceLock::new();
+
+// Closure for `pre_exec` setting the job rlimits, everything it needs is computed beforehand
+pub fn rlimits(options: &JobOptions) -> impl FnMut() -> io::Result<()> + Send + Sync + 'static {
+    let same = |value: u64| (value, value);
+    let limits = [
+        (
+            libc::RLIMIT_CPU,
+            options
+                .cpu_time
+                .map(|seconds| (seconds, seconds + CPU_GRACE_SECONDS)),
+        ),
+        (libc::RLIMIT_AS, options.address_space.map(same)),
+        (libc::RLIMIT_NOFILE, options.open_files.map(same)),
+        (libc::RLIMIT_NPROC, options.processes.map(same)),
+    ];
+    move || {
+        for (resource, limit) in limits {
+            if let Some((soft, hard)) = limit {
+                let rlimit = libc::rlimit {
+                    rlim_cur: soft as libc::rlim_t,
+                    rlim_max: hard as libc::rlim_t,
+                };
+                if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Group created for one run under the cgroup delegated to the daemon
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl Cgroup {
+    // `None` when the job doesn't ask for any cgroup limit
+    pub fn create(name: &str, options: &JobOptions) -> Result<Option<Cgroup>, String> {
+        if options.memory_max.is_none() && options.cpu_max.is_none() {
+            return Ok(None);
+        }
+        let root = DELEGATED_CGROUP
+            .get_or_init(prepare_delegated_cgroup)
+            .clone()?;
+        let path = root.join(name);
+        fs::create_dir(&path).map_err(|e| format!("Couldn't create {}: {}", path.display(), e))?;
+        let mut settings = vec![];
+        if let Some(memory_max) = options.memory_max {
+            settings.push(("memory.max", memory_max.to_string()));
+            // No swap either, the limit would be pointless otherwise
+            settings.push(("memory.swap.max", "0".to_string()));
+        }
+        if let Some(cpu_max) = options.cpu_max {
+            let quota = cpu_max * CPU_PERIOD_MICROSECONDS / 100;
+            settings.push(("cpu.max", format!("{} {}", quota, CPU_PERIOD_MICROSECONDS)));
+        }
+        let procs = settings
+            .into_iter()
+            .try_for_each(|(file, value)| {
+                // memory.swap.max is missing when swap accounting is off, which is fine
+                match fs::write(path.join(file), value) {
+                    Err(e) if file == "memory.swap.max" && e.kind() == io::ErrorKind::NotFound => {
+                        Ok(())
+                    }
+                    result => result.map_err(|e| format!("Couldn't set {}: {}", file, e)),
+                }
+            })
+            .and_then(|_| {
+                OpenOptions::new()
+                    .write(true)
+                    .open(path.join("cgroup.procs"))
+                    .map_err(|e| e.to_string())
+            });
+        match procs {
+            Ok(procs) => Ok(Some(Cgroup { path, procs })),
+            Err(e) => {
+                let _ = fs::remove_dir(&path);
+                Err(e)
+            }
+        }
+    }
+
+    // Closure for `pre_exec` moving the job process into the group
+    pub fn attach(&self) -> impl FnMut() -> io::Result<()> + Send + Sync + 'static {
+        let fd = self.procs.as_raw_fd();
+        move || {
+            if unsafe { libc::write(fd, b"0".as_ptr().cast(), 1) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    pub fn oom_killed(&self) -> bool {
+        let events = fs::read_to_string(self.path.join("memory.events")).unwrap_or_default();
+        events
+            .lines()
+            .filter_map(|line| line.strip_prefix("oom_kill "))
+            .any(|count| count.trim() != "0")
+    }
+
+    // Kill every process still in the group, even the ones that left the job's process group
+    pub fn kill(&self) {
+        if fs::write(self.path.join("cgroup.kill"), "1").is_err() {
+            return;
+        }
+        // The kill is asynchronous, give the kernel a moment before the group is removed
+        for _ in 0..50 {
+            let populated = fs::read_to_string(self.path.join("cgroup.events"))
+                .map(|events| events.contains("populated 1"))
+                .unwrap_or(false);
+            if !populated {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Only succeeds once every process of the group is gone
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.procs);
+        fs::remove_dir(&self.path)
+    }
+}
+
+// A limit the job ran into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exceeded {
+    // Killed by the kernel for going over it
+    Killed(&'static str),
+    // A call refused because of it made the job fail
+    Failed(&'static str),
+}
+
+// The limit that ended the run, if any. `cpu_time` is what the job used, `tail` the end of its
+// output: the rlimits other than the CPU one only make calls fail, the errors are told by the
+// message the job printed about them.
+pub fn exceeded_limit(
+    status: &ExitStatus,
+    cpu_time: Duration,
+    cgroup: Option<&Cgroup>,
+    options: &JobOptions,
+    tail: &str,
+) -> Option<Exceeded> {
+    if status.success() {
+        return None;
+    }
+    if cgroup.is_some_and(|cgroup| cgroup.oom_killed()) {
+        return Some(Exceeded::Killed("memory.max"));
+    }
+    // SIGKILL once the hard limit is reached, when SIGXCPU didn't stop it
+    let over_cpu_time = options
+        .cpu_time
+        .is_some_and(|seconds| cpu_time >= Duration::from_secs(seconds));
+    match status.signal() {
+        Some(libc::SIGXCPU) => return Some(Exceeded::Killed("its CPU time limit")),
+        Some(libc::SIGKILL) if over_cpu_time => {
+            return Some(Exceeded::Killed("its CPU time limit"))
+        }
+        _ => {}
+    }
+    let limits = [
+        (
+            options.address_space,
+            "Cannot allocate memory",
+            "its address space limit",
+        ),
+        (
+            options.open_files,
+            "Too many open files",
+            "its open files limit",
+        ),
+        (
+            options.processes,
+            "Resource temporarily unavailable",
+            "its processes limit",
+        ),
+    ];
+    limits
+        .into_iter()
+        .find(|(limit, error, _)| limit.is_some() && tail.contains(error))
+        .map(|(_, _, name)| Exceeded::Failed(name))
+}
+
+// Controllers can only be handed to child groups of a group holding no process, so the daemon
+// moves itself into a leaf of the cgroup it was given before enabling them.
+fn prepare_delegated_cgroup() -> Result<PathBuf, String> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup").map_err(|e| e.to_string())?;
+    let mounts = fs::read_to_string("/proc/self/mounts").map_err(|e| e.to_string())?;
+    let (root, in_leaf) = delegated_cgroup(&cgroup, &mounts)?;
+    // What systemd hands over with `Delegate=yes`: the group and the files to manage it
+    let not_writable = [
+        root.clone(),
+        root.join("cgroup.procs"),
+        root.join("cgroup.subtree_control"),
+    ]
+    .into_iter()
+    .find(|path| !writable(path));
+    if let Some(path) = not_writable {
+        return Err(format!(
+            "{} isn't delegated to the daemon, {} isn't writable",
+            root.display(),
+            path.display()
+        ));
+    }
+    let controllers = fs::read_to_string(root.join("cgroup.controllers"))
+        .map_err(|e| format!("Couldn't read the controllers of {}: {}", root.display(), e))?;
+    let controllers: Vec<&str> = controllers.split_whitespace().collect();
+    if !controllers.contains(&"memory") || !controllers.contains(&"cpu") {
+        return Err(format!(
+            "{} doesn't have the memory and cpu controllers",
+            root.display()
+        ));
+    }
+    if !in_leaf {
+        let leaf = root.join("daemon");
+        fs::create_dir_all(&leaf)
+            .and_then(|_| fs::write(leaf.join("cgroup.procs"), std::process::id().to_string()))
+            .map_err(|e| format!("Couldn't move the daemon to {}: {}", leaf.display(), e))?;
+    }
+    fs::write(root.join("cgroup.subtree_control"), "+memory +cpu")
+        .map_err(|e| format!("Couldn't enable the memory and cpu controllers: {}", e))?;
+    Ok(root)
+}
+
+// The cgroup the daemon was started in, from `/proc/self/cgroup` and `/proc/self/mounts`, and
+// whether the daemon already moved itself into its `daemon` leaf
+fn delegated_cgroup(cgroup: &str, mounts: &str) -> Result<(PathBuf, bool), String> {
+    let relative = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or("cgroup v2 isn't available")?;
+    // Usually /sys/fs/cgroup, but /sys/fs/cgroup/unified on hosts with both versions mounted
+    let mount = mounts
+        .lines()
+        .map(|line| line.split(' ').collect::<Vec<&str>>())
+        .find(|fields| fields.len() > 2 && fields[2] == "cgroup2")
+        .map(|fields| PathBuf::from(fields[1]))
+        .ok_or("cgroup v2 isn't mounted")?;
+    let mut root = mount.join(relative.trim_start_matches('/'));
+    let in_leaf = root.file_name().is_some_and(|name| name == "daemon");
+    if in_leaf {
+        root.pop();
+    }
+    // Run as root outside systemd or in a container, the daemon would take over the host's groups
+    if root == mount {
+        return Err("the daemon runs in the root cgroup, none is delegated to it".to_string());
+    }
+    Ok((root, in_leaf))
+}
+
+fn writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exceeded_limits() {
+        let options = JobOptions::parse("cpu_time=1 open_files=16").unwrap();
+        let exceeded = |status, cpu_time, tail| {
+            exceeded_limit(
+                &ExitStatus::from_raw(status),
+                cpu_time,
+                None,
+                &options,
+                tail,
+            )
+        };
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            exceeded(libc::SIGKILL, second, ""),
+            Some(Exceeded::Killed("its CPU time limit"))
+        );
+        assert_eq!(exceeded(libc::SIGKILL, second / 2, ""), None);
+        // Exit code 1
+        let failed = 1 << 8;
+        assert_eq!(
+            exceeded(failed, second / 2, "open: Too many open files\n"),
+            Some(Exceeded::Failed("its open files limit"))
+        );
+        assert_eq!(exceeded(failed, second / 2, "Cannot allocate memory"), None);
+        assert_eq!(exceeded(0, second, "Too many open files"), None);
+    }
+    #[test]
+    fn delegated_cgroups() {
+        let mounts = "sysfs /sys sysfs rw 0 0\ncgroup2 /sys/fs/cgroup cgroup2 rw 0 0\n";
+        let service = "0::/system.slice/jobs.service\n";
+        assert_eq!(
+            delegated_cgroup(service, mounts),
+            Ok((
+                PathBuf::from("/sys/fs/cgroup/system.slice/jobs.service"),
+                false
+            ))
+        );
+        assert_eq!(
+            delegated_cgroup("0::/system.slice/jobs.service/daemon\n", mounts),
+            Ok((
+                PathBuf::from("/sys/fs/cgroup/system.slice/jobs.service"),
+                true
+            ))
+        );
+        assert!(delegated_cgroup("0::/\n", mounts).is_err());
+        assert!(delegated_cgroup("0::/daemon\n", mounts).is_err());
+        assert!(delegated_cgroup("1:memory:/\n", mounts).is_err());
+        assert!(delegated_cgroup(service, "sysfs /sys sysfs rw 0 0\n").is_err());
+    }
+}
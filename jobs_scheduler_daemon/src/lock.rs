@@ -0,0 +1,66 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Longest wait for the instance being replaced, which may be draining its runs
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+pub enum LockError {
+    // Another daemon runs, with that PID when the file holds one
+    Held(Option<libc::pid_t>),
+    Io(io::Error),
+}
+
+// Exclusive lock on `path`, which holds the PID of the daemon owning it. The lock goes away with
+// the returned file, so it has to be kept open as long as the daemon runs.
+pub fn acquire(path: &Path, replace: bool) -> Result<File, LockError> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(LockError::Io)?;
+    if !try_lock(&file)? {
+        let pid = read_pid(&mut file);
+        let Some(pid) = pid.filter(|_| replace) else {
+            return Err(LockError::Held(pid));
+        };
+        if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+            return Err(LockError::Io(io::Error::last_os_error()));
+        }
+        eprintln!("Waiting for the daemon {} to shut down", pid);
+        let deadline = Instant::now() + REPLACE_TIMEOUT;
+        while !try_lock(&file)? {
+            if Instant::now() >= deadline {
+                return Err(LockError::Held(Some(pid)));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+    file.set_len(0)
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| writeln!(file, "{}", std::process::id()))
+        .map_err(LockError::Io)?;
+    Ok(file)
+}
+
+fn try_lock(file: &File) -> Result<bool, LockError> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(LockError::Io(error)),
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<libc::pid_t> {
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
@@ -0,0 +1,218 @@
+use chrono::{SecondsFormat, Utc};
+use core::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Set once the settings are known, entries logged before go to stderr
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogType {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for LogType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogType::Debug => write!(f, "debug"),
+            LogType::Info => write!(f, "info"),
+            LogType::Warn => write!(f, "warn"),
+            LogType::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl LogType {
+    pub fn parse(value: &str) -> Option<LogType> {
+        match value {
+            "debug" => Some(LogType::Debug),
+            "info" => Some(LogType::Info),
+            "warn" => Some(LogType::Warn),
+            "error" => Some(LogType::Error),
+            _ => None,
+        }
+    }
+}
+
+pub struct LogSettings {
+    // Entries below are left out
+    pub level: LogType,
+    // `None` to log to stderr, as wanted under a supervisor that keeps it
+    pub file: Option<PathBuf>,
+    // The file is rotated once it grows past it
+    pub max_size: u64,
+    // Rotated files kept, as `logs.1` for the most recent up to `logs.<keep>`
+    pub keep: usize,
+}
+
+struct Logger {
+    settings: LogSettings,
+    // Open in append mode with its current size
+    file: Option<(File, u64)>,
+}
+
+pub fn init(settings: LogSettings) -> io::Result<()> {
+    let file = match &settings.file {
+        Some(path) => Some(open(path)?),
+        None => None,
+    };
+    *LOGGER.lock().unwrap() = Some(Logger { settings, file });
+    Ok(())
+}
+
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+pub fn add_to_log(log_type: LogType, text: String) {
+    write_entry(log_type, &[], &text);
+}
+
+// Entry about a job, and one of its runs when `run_id` is set
+pub fn add_to_job_log(log_type: LogType, job: &str, run_id: Option<&str>, text: String) {
+    match run_id {
+        Some(run_id) => write_entry(log_type, &[("job", job), ("run", run_id)], &text),
+        None => write_entry(log_type, &[("job", job)], &text),
+    }
+}
+
+// One logfmt line: `time=... level=... job=... msg="..."`
+fn write_entry(log_type: LogType, fields: &[(&str, &str)], text: &str) {
+    let mut line = format!(
+        "time={} level={}",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        log_type
+    );
+    for (key, value) in fields.iter().chain(&[("msg", text)]) {
+        line += &format!(" {}={}", key, quote(value));
+    }
+    line.push('\n');
+    let mut logger = LOGGER.lock().unwrap();
+    let Some(logger) = logger.as_mut() else {
+        eprint!("{}", line);
+        return;
+    };
+    if log_type < logger.settings.level {
+        return;
+    }
+    let written = match &mut logger.file {
+        Some((file, size)) => file.write_all(line.as_bytes()).map(|_| {
+            *size += line.len() as u64;
+        }),
+        None => io::stderr().write_all(line.as_bytes()),
+    };
+    if let Err(e) = written.and_then(|_| logger.rotate()) {
+        eprintln!("Error while writing to log file: {}", e);
+        eprint!("{}", line);
+    }
+}
+
+impl Logger {
+    fn rotate(&mut self) -> io::Result<()> {
+        let (Some((_, size)), Some(path)) = (&self.file, &self.settings.file) else {
+            return Ok(());
+        };
+        if *size < self.settings.max_size {
+            return Ok(());
+        }
+        let rotated = |index: usize| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+        match self.settings.keep {
+            0 => fs::remove_file(path)?,
+            keep => {
+                let _ = fs::remove_file(rotated(keep));
+                for index in (1..keep).rev() {
+                    if rotated(index).exists() {
+                        fs::rename(rotated(index), rotated(index + 1))?;
+                    }
+                }
+                fs::rename(path, rotated(1))?;
+            }
+        }
+        self.file = Some(open(path)?);
+        Ok(())
+    }
+}
+
+// Values are quoted when they have to be, with `"`, `\` and line breaks escaped
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '=' || c == '\\');
+    if plain {
+        return value.to_string();
+    }
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_values() {
+        assert_eq!(quote("job-1"), "job-1");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("two words"), "\"two words\"");
+        assert_eq!(quote("a=b"), "\"a=b\"");
+        assert_eq!(
+            quote("say \"hi\"\\\nbye\r\t"),
+            "\"say \\\"hi\\\"\\\\\\nbye\\r\\t\""
+        );
+    }
+    #[test]
+    fn rotation() {
+        let folder = std::env::temp_dir().join(format!("logger-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("logs");
+        let rotated = |index: usize| folder.join(format!("logs.{}", index));
+        let mut logger = Logger {
+            settings: LogSettings {
+                level: LogType::Debug,
+                file: Some(path.clone()),
+                max_size: 10,
+                keep: 2,
+            },
+            file: Some(open(&path).unwrap()),
+        };
+        let write = |logger: &mut Logger, text: &str| {
+            let (file, size) = logger.file.as_mut().unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+            *size += text.len() as u64;
+            logger.rotate().unwrap();
+        };
+        write(&mut logger, "short\n");
+        assert!(!rotated(1).exists());
+        write(&mut logger, "first\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "short\nfirst\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        write(&mut logger, "second line\n");
+        write(&mut logger, "third line\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "third line\n");
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "second line\n");
+        assert!(!rotated(3).exists());
+
+        logger.settings.keep = 0;
+        write(&mut logger, "dropped line\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "third line\n");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -1,140 +1,229 @@
-use chrono::{DateTime, Utc};
-use core::fmt;
+use args::Args;
+use chrono::Utc;
 use directories::UserDirs;
-use std::fs::{self, OpenOptions};
-use std::io::{self, prelude::*};
-use std::path::Path;
-use std::process::Command;
-use std::thread;
-use utils::parse_jobs;
+use lock::LockError;
+use logger::{add_to_job_log, add_to_log, LogSettings, LogType};
+use scheduler::Scheduler;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::channel;
+use std::sync::OnceLock;
+use utils::{
+    migrate_csv_history, Config, HistoryBackend, HistoryRecord, JobsFile, JobsFiles, Retention,
+    CSV_HISTORY_FILE,
+};
+
+mod args;
+mod control;
+mod limits;
+mod lock;
+mod logger;
+mod reaper;
+mod retention;
+mod runner;
+mod scheduler;
+mod signals;
+mod users;
+mod watcher;
+
+// Read once at startup, flags included
+static CONFIG: OnceLock<Config> = OnceLock::new();
 
 fn main() {
-    let js_dir = UserDirs::new().unwrap().home_dir().join("job_scheduler");
-    let log_file = js_dir.join("logs");
-    let history_file = js_dir.join("history");
-    let jobs_file = js_dir.join("jobs");
-    // Check if files exists else create
-    if !log_file.exists() {
-        fs::create_dir_all(js_dir).expect("Not allowed to create ~/job_scheduler folder");
-        std::fs::File::create(log_file).expect("Not allowed to create ~/job_scheduler/logs file");
-    } else {
-        std::fs::File::create(log_file).expect("Not allowed to empty ~/job_scheduler/logs file");
-    }
-    if !history_file.exists() {
-        std::fs::File::create(history_file.clone())
-            .expect("Not allowed to create ~/job_scheduler/history file");
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    let mut config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration, {}", e);
+            process::exit(2);
+        }
+    };
+    // Flags win over the file
+    config.retention = Retention {
+        max_age: args.retention.max_age.or(config.retention.max_age),
+        max_runs: args.retention.max_runs.or(config.retention.max_runs),
+        max_size: args.retention.max_size.or(config.retention.max_size),
+        compress_after: args
+            .retention
+            .compress_after
+            .or(config.retention.compress_after),
+    };
+    let config = CONFIG.get_or_init(|| config);
+    let home_dir = UserDirs::new().unwrap().home_dir().to_path_buf();
+    let data_dir = &config.data_dir;
+    if let Err(e) = fs::create_dir_all(data_dir) {
+        eprintln!("Couldn't create {}: {}", data_dir.display(), e);
+        process::exit(1);
     }
-    let mut jobs = match jobs_file.exists() {
-        true => match fs::read_to_string(jobs_file) {
-            Ok(jobs_content) => parse_jobs(jobs_content),
-            Err(_) => {
-                add_to_log(
-                    LogType::ERROR,
-                    format!("Should have been able to read the jobs file"),
-                );
-                vec![]
+    // Taken first so a second daemon leaves the files of the running one alone
+    let _lock = match lock::acquire(&config.pid_file(), args.replace) {
+        Ok(lock) => lock,
+        Err(LockError::Held(pid)) => {
+            match pid {
+                Some(pid) => eprintln!(
+                    "The daemon is already running with PID {}, use --replace to restart it",
+                    pid
+                ),
+                None => eprintln!("The daemon is already running, use --replace to restart it"),
             }
-        },
-        false => {
-            add_to_log(
-                LogType::DEBUG,
-                format!("No job file found in ~/job_scheduler/"),
-            );
-            vec![]
+            process::exit(1);
+        }
+        Err(LockError::Io(e)) => {
+            eprintln!("Couldn't lock {}: {}", config.pid_file().display(), e);
+            process::exit(1);
         }
     };
-
-    jobs.sort_by_key(|j| j.next_run);
-    println!("There are {} jobs in the queue", jobs.len());
-    loop {
-        let time_to_wait = Utc::now() - jobs[0].next_run;
-        println!(
-            "next job is {} and will be run in {}",
-            jobs[0].name,
-            time_to_wait.to_string()
-        );
-        thread::sleep(time_to_wait.to_std().unwrap());
-        let string_command = jobs[0].command.clone();
-        let mut parts = string_command.split_whitespace();
-        let command = parts.next().expect("No command provided");
-        let args: Vec<&str> = parts.collect();
-        match Command::new(command).args(args).spawn() {
-            Ok(child) => {
-                if let Some(child_stderr) = child.stderr {
-                    let mut stderr_reader = io::BufReader::new(child_stderr);
-                    let mut error_message = String::new();
-                    stderr_reader.read_to_string(&mut error_message).unwrap();
-
-                    add_to_history(
-                        jobs[0].name.clone(),
-                        Utc::now(),
-                        "Error",
-                        &error_message,
-                        &history_file,
-                    );
-                } else {
-                    add_to_history(jobs[0].name.clone(), Utc::now(), "Ok", "", &history_file);
-                }
-            }
-            Err(err) => add_to_log(LogType::ERROR, err.to_string()),
+    let log_settings = LogSettings {
+        level: args
+            .log_level
+            .or(LogType::parse(&config.log.level))
+            .unwrap_or(LogType::Info),
+        file: (!args.log_stderr && !config.log.stderr).then(|| config.log_file.clone()),
+        max_size: args.log_max_size.unwrap_or(config.log.max_size),
+        keep: args.log_keep.unwrap_or(config.log.keep),
+    };
+    if let Err(e) = logger::init(log_settings) {
+        eprintln!("Couldn't open {}: {}", config.log_file.display(), e);
+        process::exit(1);
+    }
+    let mut history = match config.history_backend.open(&config.history_file) {
+        Ok(history) => history,
+        Err(e) => {
+            add_to_log(LogType::Error, format!("Couldn't open the history, {}", e));
+            process::exit(1);
+        }
+    };
+    let csv_history_file = data_dir.join(CSV_HISTORY_FILE);
+    if config.history_backend == HistoryBackend::Sqlite && csv_history_file.exists() {
+        match migrate_csv_history(&csv_history_file, history.as_mut()) {
+            Ok(moved) => add_to_log(
+                LogType::Info,
+                format!(
+                    "Moved {} history entries to {}",
+                    moved,
+                    config.history_file.display()
+                ),
+            ),
+            Err(e) => add_to_log(
+                LogType::Error,
+                format!(
+                    "Couldn't move the history to {}: {}",
+                    config.history_file.display(),
+                    e
+                ),
+            ),
         }
-        jobs[0].get_next_run();
-        jobs.sort_by_key(|j| j.next_run);
     }
-}
+    let (JobsFile { defaults, jobs, .. }, _) = load_jobs(&config.jobs_file, &config.jobs_dir);
 
-fn add_to_history(
-    name: String,
-    timestamp: DateTime<Utc>,
-    status: &str,
-    error_message: &str,
-    history_file: &Path,
-) {
-    let line = name + "," + &timestamp.to_string() + "," + status + "," + error_message;
-    match OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(history_file)
-    {
-        Ok(mut file) => {
-            if let Err(e) = writeln!(file, "{}", line) {
-                add_to_log(
-                    LogType::ERROR,
-                    format!("Couldn't write to history file: {}", e),
+    let paths = runner::Paths {
+        home_dir,
+        runs_dir: config.runs_dir.clone(),
+        history_file: config.history_file.clone(),
+        jobs_file: config.jobs_file.clone(),
+        jobs_dir: config.jobs_dir.clone(),
+    };
+
+    reaper::start();
+    retention::start(config.retention.clone(), paths.history_file.clone());
+    let (events, receiver) = channel();
+    runner::report_to(events.clone());
+    // Runs missed while the daemon was down, counted from the last entry of each job
+    let now = Utc::now();
+    for job in jobs.iter().filter(|job| job.enabled) {
+        let last_run = match history.last_started(&job.name) {
+            Ok(last_run) => last_run,
+            Err(e) => {
+                add_to_job_log(
+                    LogType::Error,
+                    &job.name,
+                    None,
+                    format!("Couldn't read its history: {}", e),
                 );
+                None
             }
+        };
+        if let Some(last_run) = last_run {
+            runner::catch_up(job, &defaults, last_run, now, &paths);
         }
-        Err(_) => add_to_log(LogType::ERROR, format!("Couldn't open history file:")),
     }
+    add_to_log(
+        LogType::Info,
+        format!("There are {} jobs in the queue", jobs.len()),
+    );
+    watcher::watch_jobs_files(&paths.jobs_file, &paths.jobs_dir, events.clone());
+    let socket = config.socket();
+    if let Err(e) = control::listen(&socket, events.clone()) {
+        add_to_log(
+            LogType::Error,
+            format!(
+                "Couldn't open the control socket {}: {}",
+                socket.display(),
+                e
+            ),
+        );
+    }
+    if let Err(e) = signals::start(events) {
+        add_to_log(
+            LogType::Error,
+            format!("Couldn't handle the signals: {}", e),
+        );
+    }
+    Scheduler::new(jobs, defaults, paths, receiver).run();
+    add_to_log(LogType::Info, "Shutting down".to_string());
+    let _ = fs::remove_file(&socket);
+    runner::shut_down(args.drain_timeout);
 }
 
-enum LogType {
-    DEBUG,
-    ERROR,
+pub fn config() -> &'static Config {
+    CONFIG.get().expect("The config is read first")
 }
 
-impl fmt::Display for LogType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            LogType::DEBUG => write!(f, "DEBUG"),
-            LogType::ERROR => write!(f, "ERROR"),
-        }
+// Files that can't be used and jobs that can't run are left out with an error, the paths of
+// the files left out come along
+pub fn load_jobs(jobs_file: &Path, jobs_dir: &Path) -> (JobsFile, Vec<PathBuf>) {
+    let (files, errors) = JobsFiles::load(jobs_file, jobs_dir);
+    for e in &errors {
+        add_to_log(LogType::Error, format!("Left out a jobs file, {}", e));
+    }
+    if !jobs_file.exists() {
+        add_to_log(
+            LogType::Info,
+            format!("No jobs file found at {}", jobs_file.display()),
+        );
     }
+    // The config fills in what the files leave unset, like the time zone the next runs follow
+    let mut content = files.merge(&config().job_defaults());
+    let defaults = content.defaults.clone();
+    content
+        .jobs
+        .retain(|job| match runner::check(job, &defaults) {
+            Ok(()) => true,
+            Err(e) => {
+                add_to_job_log(LogType::Error, &job.name, None, e);
+                false
+            }
+        });
+    (content, errors.into_iter().map(|e| e.path).collect())
 }
 
-fn add_to_log(log_type: LogType, text: String) {
-    let log_file = UserDirs::new()
-        .unwrap()
-        .home_dir()
-        .join("job_scheduler")
-        .join("logs");
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(log_file)
-        .unwrap();
-    if let Err(e) = writeln!(file, "{}: {}", log_type, text) {
-        println!("Error while writing to log file: {}", e);
+// Opened for each run, runs are far enough apart
+fn add_to_history(record: &HistoryRecord, history_file: &Path) {
+    let appended = config()
+        .history_backend
+        .open(history_file)
+        .and_then(|mut history| history.append(std::slice::from_ref(record)));
+    if let Err(e) = appended {
+        add_to_log(
+            LogType::Error,
+            format!("Couldn't write to history file: {}", e),
+        );
     }
-    println!("{}: {}", log_type, text);
 }
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+// Every child of the daemon is waited for here: the jobs it started, and the processes they
+// left behind, which the kernel re-parents to the daemon as it is a child subreaper.
+struct Reaper {
+    children: Mutex<HashMap<u32, Sender<Exit>>>,
+    spawned: Condvar,
+}
+
+// How a child ended, with the CPU time it and the children it waited for used
+pub struct Exit {
+    pub status: ExitStatus,
+    pub cpu_time: Duration,
+}
+
+static REAPER: OnceLock<Reaper> = OnceLock::new();
+
+pub fn start() {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        crate::add_to_log(
+            crate::LogType::Error,
+            format!(
+                "Couldn't become a subreaper, orphans of jobs won't be reaped: {}",
+                io::Error::last_os_error()
+            ),
+        );
+    }
+    let reaper = REAPER.get_or_init(|| Reaper {
+        children: Mutex::new(HashMap::new()),
+        spawned: Condvar::new(),
+    });
+    thread::spawn(move || reaper.run());
+}
+
+// Spawn `command`, how it ended is sent on the returned receiver
+pub fn spawn(command: &mut Command) -> io::Result<(Child, Receiver<Exit>)> {
+    let reaper = REAPER.get().expect("the reaper should be started first");
+    // Holding the lock while spawning keeps the reaper from taking the child before it is known
+    let mut children = reaper.children.lock().unwrap();
+    let child = command.spawn()?;
+    let (sender, receiver) = channel();
+    children.insert(child.id(), sender);
+    reaper.spawned.notify_one();
+    Ok((child, receiver))
+}
+
+impl Reaper {
+    fn run(&self) {
+        loop {
+            // Look at the next exited child without reaping it: a child whose exec failed is
+            // reaped by `Command::spawn` itself, which must not find it already gone.
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            let result =
+                unsafe { libc::waitid(libc::P_ALL, 0, &mut info, libc::WEXITED | libc::WNOWAIT) };
+            if result != 0 {
+                match io::Error::last_os_error().raw_os_error() {
+                    Some(libc::ECHILD) => {
+                        let children = self.children.lock().unwrap();
+                        let _ = self
+                            .spawned
+                            .wait_timeout(children, Duration::from_secs(1))
+                            .unwrap();
+                    }
+                    _ => thread::sleep(Duration::from_millis(100)),
+                }
+                continue;
+            }
+            let pid = unsafe { info.si_pid() };
+            let mut children = self.children.lock().unwrap();
+            let mut status = 0;
+            let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+            let flags = match children.contains_key(&(pid as u32)) {
+                true => 0,
+                // Either an orphan or a child `Command::spawn` already reaped
+                false => libc::WNOHANG,
+            };
+            if unsafe { libc::wait4(pid, &mut status, flags, &mut usage) } == pid {
+                if let Some(sender) = children.remove(&(pid as u32)) {
+                    let seconds = |time: libc::timeval| {
+                        Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+                    };
+                    let _ = sender.send(Exit {
+                        status: ExitStatus::from_raw(status),
+                        cpu_time: seconds(usage.ru_utime) + seconds(usage.ru_stime),
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{add_to_log, config, LogType};
+use chrono::Utc;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use utils::{compress_output, Retention};
+
+// Time between two passes over the history
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Apply `retention` now and then every hour, nothing to do when it keeps everything
+pub fn start(retention: Retention, history_file: PathBuf) {
+    if retention.is_empty() {
+        return;
+    }
+    thread::spawn(move || loop {
+        if let Err(e) = apply(&retention, &history_file) {
+            add_to_log(
+                LogType::Error,
+                format!("Couldn't apply the history retention: {}", e),
+            );
+        }
+        thread::sleep(INTERVAL);
+    });
+}
+
+// Old outputs are compressed first so they count for less against the size limit. The runs
+// go by one at a time, only the ones to change are kept.
+fn apply(retention: &Retention, history_file: &Path) -> Result<(), String> {
+    let mut history = config().history_backend.open(history_file)?;
+    let now = Utc::now();
+    let mut expiry = retention.expiry(now);
+    let (mut compressed, mut expired) = (vec![], vec![]);
+    history.scan(&mut |mut record| {
+        if let Some(output) = record
+            .output
+            .clone()
+            .filter(|_| retention.compresses(&record, now))
+        {
+            match compress_output(&output) {
+                Ok(path) => {
+                    record.output = Some(path);
+                    compressed.push(record.clone());
+                }
+                // Removed by hand
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => add_to_log(
+                    LogType::Error,
+                    format!("Couldn't compress {}: {}", output.display(), e),
+                ),
+            }
+        }
+        let size = record
+            .output
+            .as_ref()
+            .and_then(|output| fs::metadata(output).ok())
+            .map_or(0, |metadata| metadata.len());
+        if expiry.is_expired(&record, size) {
+            expired.push(record);
+        }
+    })?;
+    for record in &compressed {
+        if let Some(output) = &record.output {
+            history.set_output(record, output)?;
+        }
+    }
+    for output in expired.iter().filter_map(|record| record.output.as_ref()) {
+        match fs::remove_file(output) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => add_to_log(
+                LogType::Error,
+                format!("Couldn't remove {}: {}", output.display(), e),
+            ),
+            _ => {}
+        }
+    }
+    history.remove(&expired)?;
+    if !compressed.is_empty() || !expired.is_empty() {
+        add_to_log(
+            LogType::Info,
+            format!(
+                "History retention: {} run(s) removed, {} output(s) compressed",
+                expired.len(),
+                compressed.len()
+            ),
+        );
+    }
+    Ok(())
+}
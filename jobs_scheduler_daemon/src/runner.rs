@@ -0,0 +1,641 @@
+use crate::limits::{self, Cgroup, Exceeded};
+use crate::reaper;
+use crate::scheduler::Event;
+use crate::users::{self, Identity};
+use crate::{add_to_history, add_to_job_log, add_to_log, config, LogType};
+use chrono::{DateTime, Utc};
+use std::fs::{self, File};
+use std::io::{self, prelude::*, PipeReader};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use utils::ipc::RunState;
+use utils::{
+    format_duration, missed_runs, parse_env_file, HistoryRecord, Job, JobOptions, RunStatus,
+    Trigger, DEFAULT_SHELL,
+};
+
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+// Bigger outputs are cut, the rest of the pipe is still read so the job never blocks on it
+const MAX_OUTPUT_SIZE: usize = 1024 * 1024;
+// Last bytes of the output kept in the history file
+const TAIL_SIZE: usize = 500;
+// Time processes get to exit after SIGTERM
+const KILL_GRACE: Duration = Duration::from_secs(5);
+// Logged for the jobs whose schedule only falls on times the clocks skip
+pub const NEVER_RUNS: &str = "No time of its schedule comes in its time zone, it won't run";
+
+// Runs in progress, an entry is removed once its history entry is written
+static RUNS: Mutex<Vec<Run>> = Mutex::new(vec![]);
+// Set on shutdown, no run starts anymore
+static STOPPING: AtomicBool = AtomicBool::new(false);
+// Tells apart the runs started within the same millisecond
+static NEXT_RUN: AtomicU64 = AtomicU64::new(1);
+// Told about every finished run, so the jobs that come after it can start
+static FINISHED: OnceLock<Sender<Event>> = OnceLock::new();
+
+struct Run {
+    id: String,
+    job: String,
+    started: DateTime<Utc>,
+    // The job leads its own process group, so its id is also the group one
+    group: libc::pid_t,
+    // Why the daemon killed it, if it did
+    stopped: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Paths {
+    pub home_dir: PathBuf,
+    pub runs_dir: PathBuf,
+    pub history_file: PathBuf,
+    pub jobs_file: PathBuf,
+    pub jobs_dir: PathBuf,
+}
+
+pub struct Started {
+    pub run_id: String,
+    // Ends once the history entry of the run is written
+    pub thread: JoinHandle<()>,
+}
+
+// Start `job` and return right away, a thread waits for it and writes its history entry.
+// `None` when the job couldn't even be started, which is already in the history.
+// `scheduled` is when the run was due, `args` replace the arguments of the command, see
+// `Job::command_line_with`.
+pub fn start(
+    job: &Job,
+    defaults: &JobOptions,
+    paths: &Paths,
+    trigger: Trigger,
+    scheduled: Option<DateTime<Utc>>,
+    args: Option<&[String]>,
+) -> Option<Started> {
+    start_attempt(job, defaults, paths, trigger, scheduled, args, 1)
+}
+
+// A run that failed or timed out starts the next attempt from its own thread, up to `retries`
+// more of them. The jobs that come after it only hear about the last one.
+fn start_attempt(
+    job: &Job,
+    defaults: &JobOptions,
+    paths: &Paths,
+    trigger: Trigger,
+    scheduled: Option<DateTime<Utc>>,
+    args: Option<&[String]>,
+    attempt: u32,
+) -> Option<Started> {
+    if STOPPING.load(Ordering::SeqCst) {
+        return None;
+    }
+    let started = Utc::now();
+    // Jobs due at the same time start within the same millisecond
+    let run_id = format!(
+        "{}-{}",
+        started.format("%Y%m%d-%H%M%S-%3f"),
+        NEXT_RUN.fetch_add(1, Ordering::Relaxed)
+    );
+    let name = job.name.clone();
+    let output_path = paths
+        .runs_dir
+        .join(&job.name)
+        .join(format!("{}.log", run_id));
+    let history_file = paths.history_file.clone();
+    let mut record = HistoryRecord {
+        run_id: run_id.clone(),
+        name: name.clone(),
+        scheduled,
+        started,
+        ended: started,
+        exit_code: None,
+        status: RunStatus::Error,
+        trigger,
+        attempt,
+        output: None,
+        message: String::new(),
+    };
+    let cgroup = match Cgroup::create(
+        &format!("{}-{}", job.name, run_id),
+        &job.options.inherit(defaults),
+    ) {
+        Ok(cgroup) => cgroup,
+        Err(e) => return not_started(record, e, &history_file),
+    };
+    let built = build_command(job, defaults, args, cgroup.as_ref(), &paths.home_dir);
+    let (mut command, reader) = match built {
+        Ok(command) => command,
+        Err(e) => return not_started(record, e, &history_file),
+    };
+    let spawned = reaper::spawn(&mut command);
+    // The command holds the write end of the output pipe, drop it so reading ends with the job
+    drop(command);
+    let (child, exited) = match spawned {
+        Ok(spawned) => spawned,
+        Err(e) => return not_started(record, e.to_string(), &history_file),
+    };
+    let options = job.options.inherit(defaults);
+    let (timeout, retries) = (options.timeout, options.retries.unwrap_or_default());
+    let retry = (
+        job.clone(),
+        defaults.clone(),
+        paths.clone(),
+        args.map(<[String]>::to_vec),
+    );
+    let group = child.id() as libc::pid_t;
+    RUNS.lock().unwrap().push(Run {
+        id: run_id.clone(),
+        job: name.clone(),
+        started,
+        group,
+        stopped: None,
+    });
+    add_to_job_log(
+        LogType::Debug,
+        &name,
+        Some(&run_id),
+        format!("Started, triggered by {}", trigger),
+    );
+    let id = run_id.clone();
+    let thread = thread::spawn(move || {
+        let (output_sender, output_receiver) = channel();
+        {
+            let output_path = output_path.clone();
+            thread::spawn(move || output_sender.send(capture_output(reader, &output_path)));
+        }
+        let mut timed_out = false;
+        let exit = match timeout {
+            Some(seconds) => match exited.recv_timeout(Duration::from_secs(seconds)) {
+                Err(RecvTimeoutError::Timeout) => {
+                    timed_out = true;
+                    terminate_group(group);
+                    exited.recv().ok()
+                }
+                exit => exit.ok(),
+            },
+            None => exited.recv().ok(),
+        };
+        let status = exit.as_ref().map(|exit| exit.status);
+        // Whatever the job started in the background goes with it
+        if group_exists(group) {
+            add_to_job_log(
+                LogType::Warn,
+                &name,
+                Some(&run_id),
+                "Killing the processes it left behind".to_string(),
+            );
+            terminate_group(group);
+        }
+        if let Some(cgroup) = &cgroup {
+            cgroup.kill();
+        }
+        // A process that left the group may still hold the output pipe, don't wait for it forever
+        let (tail, written) = output_receiver.recv_timeout(KILL_GRACE).unwrap_or_default();
+        let output = written.then_some(output_path.as_path());
+        let exceeded = exit.and_then(|exit| {
+            limits::exceeded_limit(
+                &exit.status,
+                exit.cpu_time,
+                cgroup.as_ref(),
+                &options,
+                &tail,
+            )
+        });
+        let status_code = status.and_then(|status| status.code());
+        if let Some(Err(e)) = cgroup.map(Cgroup::remove) {
+            add_to_job_log(
+                LogType::Error,
+                &name,
+                Some(&run_id),
+                format!("Couldn't remove its cgroup: {}", e),
+            );
+        }
+        let stopped = {
+            let runs = RUNS.lock().unwrap();
+            let run = runs.iter().find(|run| run.id == run_id);
+            run.and_then(|run| run.stopped.clone())
+        };
+        let (status, message) = match (status, exceeded) {
+            _ if stopped.is_some() => (RunStatus::Killed, stopped.unwrap_or_default()),
+            _ if timed_out => {
+                let message = format!(
+                    "Timed out after {}",
+                    format_duration(timeout.unwrap_or_default())
+                );
+                (RunStatus::Timeout, message)
+            }
+            (Some(_), Some(Exceeded::Killed(limit))) => {
+                (RunStatus::Killed, format!("Killed for exceeding {}", limit))
+            }
+            (Some(_), Some(Exceeded::Failed(limit))) => {
+                (RunStatus::Error, format!("Failed on {}: {}", limit, tail))
+            }
+            (Some(status), None) if status.success() => (RunStatus::Success, tail),
+            (Some(status), None) => match tail.is_empty() {
+                true => (RunStatus::Error, status.to_string()),
+                false => (RunStatus::Error, tail),
+            },
+            (None, _) => {
+                let message = "Lost track of the job process";
+                add_to_job_log(LogType::Error, &name, Some(&run_id), message.to_string());
+                (RunStatus::Error, message.to_string())
+            }
+        };
+        add_to_job_log(
+            LogType::Info,
+            &name,
+            Some(&run_id),
+            format!("Ended with {}", status),
+        );
+        record.ended = Utc::now();
+        record.exit_code = status_code;
+        record.status = status;
+        record.output = output.map(Path::to_path_buf);
+        record.message = message;
+        add_to_history(&record, &history_file);
+        RUNS.lock().unwrap().retain(|run| run.id != run_id);
+        let failed = matches!(status, RunStatus::Error | RunStatus::Timeout);
+        if failed && u64::from(attempt) <= retries && !STOPPING.load(Ordering::SeqCst) {
+            add_to_job_log(
+                LogType::Info,
+                &name,
+                Some(&run_id),
+                format!("Retrying, attempt {} of {}", attempt + 1, retries + 1),
+            );
+            let (job, defaults, paths, args) = retry;
+            let next = start_attempt(
+                &job,
+                &defaults,
+                &paths,
+                trigger,
+                scheduled,
+                args.as_deref(),
+                attempt + 1,
+            );
+            if let Some(next) = next {
+                let _ = next.thread.join();
+            }
+            return;
+        }
+        notify(&record);
+        finished(&name, status == RunStatus::Success, trigger, scheduled);
+    });
+    Some(Started { run_id: id, thread })
+}
+
+// Where `Event::Finished` goes once a run is over, set before any job starts
+pub fn report_to(events: Sender<Event>) {
+    let _ = FINISHED.set(events);
+}
+
+fn finished(job: &str, success: bool, trigger: Trigger, scheduled: Option<DateTime<Utc>>) {
+    if let Some(events) = FINISHED.get() {
+        let _ = events.send(Event::Finished {
+            job: job.to_string(),
+            success,
+            trigger,
+            scheduled,
+        });
+    }
+}
+
+fn not_started(mut record: HistoryRecord, message: String, history_file: &Path) -> Option<Started> {
+    add_to_job_log(
+        LogType::Error,
+        &record.name,
+        Some(&record.run_id),
+        message.clone(),
+    );
+    record.ended = Utc::now();
+    record.message = message;
+    add_to_history(&record, history_file);
+    notify(&record);
+    finished(&record.name, false, record.trigger, record.scheduled);
+    None
+}
+
+// Run the notification command of the config if the status of the run asks for it
+fn notify(record: &HistoryRecord) {
+    let notifications = &config().notifications;
+    let Some(command_line) = &notifications.command else {
+        return;
+    };
+    if !notifications.on.is_met(record.status == RunStatus::Success) {
+        return;
+    }
+    let mut command = Command::new(config().shell.as_deref().unwrap_or(DEFAULT_SHELL));
+    command
+        .arg("-c")
+        .arg(command_line)
+        .env("JOB", &record.name)
+        .env("RUN_ID", &record.run_id)
+        .env("STATUS", record.status.to_string())
+        .env("MESSAGE", &record.message)
+        .env("OUTPUT", record.output.as_deref().unwrap_or(Path::new("")))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Err(e) = reaper::spawn(&mut command) {
+        add_to_job_log(
+            LogType::Error,
+            &record.name,
+            Some(&record.run_id),
+            format!("Couldn't run the notification command: {}", e),
+        );
+    }
+}
+
+// Whether another run may start under `max_concurrent`
+pub fn has_room() -> bool {
+    config()
+        .max_concurrent
+        .is_none_or(|max| RUNS.lock().unwrap().len() < max)
+}
+
+pub fn running() -> Vec<RunState> {
+    RUNS.lock()
+        .unwrap()
+        .iter()
+        .map(|run| RunState {
+            run_id: run.id.clone(),
+            job: run.job.clone(),
+            started: run.started,
+        })
+        .collect()
+}
+
+// Kill the run and everything it started, `false` when no such run is in progress
+pub fn cancel(run_id: &str) -> bool {
+    let mut runs = RUNS.lock().unwrap();
+    let Some(run) = runs.iter_mut().find(|run| run.id == run_id) else {
+        return false;
+    };
+    run.stopped = Some("Cancelled".to_string());
+    let group = run.group;
+    thread::spawn(move || terminate_group(group));
+    true
+}
+
+// Stop starting runs and give the ones in progress `drain_timeout` to end, the remaining ones are
+// killed. Returns once every run has its history entry.
+pub fn shut_down(drain_timeout: Duration) {
+    STOPPING.store(true, Ordering::SeqCst);
+    let in_progress = RUNS.lock().unwrap().len();
+    if in_progress > 0 {
+        add_to_log(
+            LogType::Info,
+            format!(
+                "Waiting up to {} for {} run(s) to end",
+                format_duration(drain_timeout.as_secs()),
+                in_progress
+            ),
+        );
+    }
+    if wait_for_runs(drain_timeout) {
+        return;
+    }
+    let groups: Vec<libc::pid_t> = RUNS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .map(|run| {
+            run.stopped = Some("Interrupted by the daemon shutdown".to_string());
+            run.group
+        })
+        .collect();
+    add_to_log(
+        LogType::Warn,
+        format!("Killing {} run(s) still in progress", groups.len()),
+    );
+    let killers: Vec<JoinHandle<()>> = groups
+        .into_iter()
+        .map(|group| thread::spawn(move || terminate_group(group)))
+        .collect();
+    for killer in killers {
+        let _ = killer.join();
+    }
+    // Each run may still wait `KILL_GRACE` for its output before writing its entry
+    if !wait_for_runs(KILL_GRACE * 2) {
+        add_to_log(
+            LogType::Error,
+            "Some runs didn't write their history entry".to_string(),
+        );
+    }
+}
+
+// Whether every run ended within `timeout`
+fn wait_for_runs(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !RUNS.lock().unwrap().is_empty() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+// Run `job` for the occurrences missed between `since` and `now` its `catch_up` option keeps,
+// one after the other so they don't pile up. Each waits for a slot when `max_concurrent` runs
+// are in progress.
+pub fn catch_up(
+    job: &Job,
+    defaults: &JobOptions,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+    paths: &Paths,
+) {
+    let missed = job.missed_runs(defaults, since, now);
+    let Some(first) = missed.first() else {
+        // Only `catch_up=skip` keeps none of them
+        let tz = job.time_zone(defaults);
+        if let Some(first) = missed_runs(&job.cron, since, now, 1, tz).first() {
+            add_to_job_log(
+                LogType::Warn,
+                &job.name,
+                None,
+                format!("Skipped the runs missed since {}, catch_up=skip", first),
+            );
+        }
+        return;
+    };
+    add_to_job_log(
+        LogType::Info,
+        &job.name,
+        None,
+        format!(
+            "Catching up on {} missed run(s) since {}",
+            missed.len(),
+            first
+        ),
+    );
+    let (job, defaults, paths) = (job.clone(), defaults.clone(), paths.clone());
+    thread::spawn(move || {
+        for scheduled in missed {
+            while !has_room() && !STOPPING.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_secs(1));
+            }
+            let trigger = Trigger::CatchUp;
+            if let Some(started) = start(&job, &defaults, &paths, trigger, Some(scheduled), None) {
+                let _ = started.thread.join();
+            }
+        }
+    });
+}
+
+// SIGTERM to every process of the group, then SIGKILL to the ones still there after a while
+fn terminate_group(group: libc::pid_t) {
+    unsafe { libc::killpg(group, libc::SIGTERM) };
+    let deadline = Instant::now() + KILL_GRACE;
+    while group_exists(group) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+    }
+    if group_exists(group) {
+        unsafe { libc::killpg(group, libc::SIGKILL) };
+    }
+}
+
+fn group_exists(group: libc::pid_t) -> bool {
+    unsafe { libc::killpg(group, 0) == 0 }
+}
+
+// Check what can be checked before the first run, so a bad job is reported when loaded
+pub fn check(job: &Job, defaults: &JobOptions) -> Result<(), String> {
+    let options = job.options.inherit(defaults);
+    if options.user.is_some() || options.group.is_some() {
+        Identity::resolve(options.user.as_deref(), options.group.as_deref())?;
+    }
+    if job.never_runs() {
+        return Err(NEVER_RUNS.to_string());
+    }
+    Ok(())
+}
+
+// Jobs start in the home directory like cron does, whatever the daemon's own directory is.
+// Relative `cwd` and `env_file` paths are taken from there too.
+fn build_command(
+    job: &Job,
+    defaults: &JobOptions,
+    args: Option<&[String]>,
+    cgroup: Option<&Cgroup>,
+    home_dir: &Path,
+) -> Result<(Command, PipeReader), String> {
+    let options = job.options.inherit(defaults);
+    let command_line = job.command_line_with(defaults, args);
+    let mut command = Command::new(&command_line[0]);
+    command.args(&command_line[1..]);
+    if !options.inherit_env.unwrap_or(true) {
+        command.env_clear();
+        command.env("PATH", DEFAULT_PATH);
+        command.env("HOME", home_dir);
+    }
+    let identity = match options.user.is_some() || options.group.is_some() {
+        true => Some(Identity::resolve(
+            options.user.as_deref(),
+            options.group.as_deref(),
+        )?),
+        false => None,
+    };
+    // A job run as another user starts from that user's home, with its variables
+    let home_dir = match identity.as_ref().and_then(|i| i.account.as_ref()) {
+        Some(account) => {
+            command
+                .env("HOME", &account.home)
+                .env("USER", &account.name)
+                .env("LOGNAME", &account.name);
+            account.home.clone()
+        }
+        None => home_dir.to_path_buf(),
+    };
+    // A group of its own lets the daemon kill everything the job started.
+    // Joining the cgroup and lowering limits must happen before giving up root.
+    command.process_group(0);
+    unsafe {
+        if let Some(cgroup) = cgroup {
+            command.pre_exec(cgroup.attach());
+        }
+        command.pre_exec(limits::rlimits(&options));
+        if let Some(identity) = identity.filter(|_| users::is_root()) {
+            command.pre_exec(move || identity.apply());
+        }
+    }
+    if let Some(env_file) = &options.env_file {
+        let env_file = home_dir.join(env_file);
+        let content = fs::read_to_string(&env_file)
+            .map_err(|e| format!("Couldn't read {}: {}", env_file.display(), e))?;
+        let env = parse_env_file(&content).map_err(|e| format!("{}: {}", env_file.display(), e))?;
+        command.envs(env);
+    }
+    command.envs(options.env);
+    let cwd = home_dir.join(options.cwd.unwrap_or_default());
+    if !cwd.is_dir() {
+        return Err(format!("working directory {} doesn't exist", cwd.display()));
+    }
+    command.current_dir(cwd);
+
+    // stdout and stderr share one pipe so the log keeps their order
+    let (reader, writer) = io::pipe().map_err(|e| e.to_string())?;
+    let error_writer = writer.try_clone().map_err(|e| e.to_string())?;
+    command
+        .stdin(Stdio::null())
+        .stdout(writer)
+        .stderr(error_writer);
+    Ok((command, reader))
+}
+
+// Copy the job output to `output_path`, returns its tail and whether the file was written
+fn capture_output(mut reader: PipeReader, output_path: &Path) -> (String, bool) {
+    let mut file = match output_path.parent().map(fs::create_dir_all) {
+        Some(Ok(())) => match File::create(output_path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                add_to_log(
+                    LogType::Error,
+                    format!("Couldn't create {}: {}", output_path.display(), e),
+                );
+                None
+            }
+        },
+        _ => {
+            add_to_log(
+                LogType::Error,
+                format!("Couldn't create the folder of {}", output_path.display()),
+            );
+            None
+        }
+    };
+    let mut buffer = [0; 8192];
+    let mut tail: Vec<u8> = vec![];
+    let mut size = 0;
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        let chunk = &buffer[..count];
+        if let Some(output) = file.as_mut() {
+            let kept = chunk.len().min(MAX_OUTPUT_SIZE.saturating_sub(size));
+            let mut result = output.write_all(&chunk[..kept]);
+            if kept < chunk.len() && size < MAX_OUTPUT_SIZE {
+                result = result.and_then(|_| writeln!(output, "\n[output truncated]"));
+            }
+            if result.is_err() {
+                file = None;
+            }
+        }
+        size += count;
+        tail.extend_from_slice(chunk);
+        if tail.len() > 2 * TAIL_SIZE {
+            tail.drain(..tail.len() - TAIL_SIZE);
+        }
+    }
+    let start = tail.len().saturating_sub(TAIL_SIZE);
+    let tail = String::from_utf8_lossy(&tail[start..]).trim().to_string();
+    (tail, file.is_some())
+}
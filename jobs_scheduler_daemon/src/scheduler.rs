@@ -0,0 +1,573 @@
+use crate::runner::{self, Paths};
+use crate::{add_to_job_log, add_to_log, config, load_jobs, LogType};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{self, Instant};
+use utils::ipc::{Command, JobState, Reply, Status};
+use utils::{
+    get_next_run_from_in, update_jobs_file, Condition, Job, JobOptions, JobsFile, JobsFiles,
+    Trigger,
+};
+
+// Longest nap between two looks at the wall clock, so a jump is noticed quickly
+const TICK: time::Duration = time::Duration::from_secs(1);
+// Gap between the wall clock and the monotonic one taken for a jump rather than jitter
+const CLOCK_JUMP: time::Duration = time::Duration::from_secs(5);
+// A run started that many seconds after it was due goes through the catch-up policy
+const LATE_SECONDS: i64 = 60;
+
+// Sent to the scheduler to wake it up before the next run is due
+pub enum Event {
+    // The jobs file changed or SIGHUP was received
+    Reload,
+    // SIGTERM or SIGINT, the scheduler stops and leaves the draining to its caller
+    Shutdown,
+    // Received on the control socket, the reply goes back on the sender
+    Command(Command, Sender<Reply>),
+    // A run is over, the jobs set to come after it may start. `scheduled` is when it was due.
+    Finished {
+        job: String,
+        success: bool,
+        trigger: Trigger,
+        scheduled: Option<DateTime<Utc>>,
+    },
+}
+
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    defaults: JobOptions,
+    paths: Paths,
+    events: Receiver<Event>,
+    // Jobs stay scheduled but none runs
+    paused: bool,
+    started: DateTime<Utc>,
+    // Outcomes the dependent jobs wait for
+    waiting: Waiting,
+    // Cycle of the run each dependent job was last started for, handed on to the jobs that
+    // come after it in turn
+    cycles: HashMap<String, Cycle>,
+    // Dependent jobs to start once their delay is over
+    delayed: Vec<(DateTime<Utc>, String)>,
+    // Runs due while `max_concurrent` runs were in progress, started in order as others end
+    queued: VecDeque<(String, Trigger, Option<DateTime<Utc>>)>,
+}
+
+impl Scheduler {
+    pub fn new(
+        mut jobs: Vec<Job>,
+        defaults: JobOptions,
+        paths: Paths,
+        events: Receiver<Event>,
+    ) -> Scheduler {
+        jobs.sort_by_key(|job| job.next_run);
+        Scheduler {
+            jobs,
+            defaults,
+            paths,
+            events,
+            paused: false,
+            started: Utc::now(),
+            waiting: Waiting::default(),
+            cycles: HashMap::new(),
+            delayed: vec![],
+            queued: VecDeque::new(),
+        }
+    }
+
+    // Returns once asked to shut down
+    pub fn run(&mut self) {
+        self.print_next_run();
+        loop {
+            let wall_start = Utc::now();
+            let start = Instant::now();
+            // Without any job there is nothing to wait for but events and the clock
+            let next_run = self.jobs.first().map(|job| job.next_run);
+            let next_delayed = self.delayed.iter().map(|(at, _)| *at).min();
+            let timeout = match next_run.into_iter().chain(next_delayed).min() {
+                Some(next) => (next - wall_start).to_std().unwrap_or_default().min(TICK),
+                None => TICK,
+            };
+            match self.events.recv_timeout(timeout) {
+                Ok(Event::Reload) => self.reload(),
+                Ok(Event::Shutdown) => return,
+                Ok(Event::Command(command, reply)) => {
+                    let _ = reply.send(self.handle(command));
+                }
+                Ok(Event::Finished {
+                    job,
+                    success,
+                    trigger,
+                    scheduled,
+                }) => self.finished(&job, success, trigger, scheduled),
+                Err(RecvTimeoutError::Timeout) => {}
+                // Nothing can wake the scheduler anymore, a plain sleep does the same
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+            }
+            // The monotonic clock ignores both clock changes and the time spent suspended
+            let jump =
+                (Utc::now() - wall_start).num_milliseconds() - start.elapsed().as_millis() as i64;
+            if jump < -(CLOCK_JUMP.as_millis() as i64) {
+                self.clock_went_back(-jump / 1000);
+            }
+            self.start_due_jobs();
+        }
+    }
+
+    // Start every job whose run is due, jobs found late go through their catch-up policy
+    fn start_due_jobs(&mut self) {
+        self.start_queued();
+        let now = Utc::now();
+        let mut started = false;
+        let mut due = vec![];
+        for job in self.jobs.iter_mut().filter(|job| job.next_run <= now) {
+            if self.paused || !job.enabled {
+                // Still moves on to its next run, it resumes from there
+            } else if now - job.next_run > Duration::seconds(LATE_SECONDS) {
+                // Woken long after the run was due: the host was asleep or the clock jumped
+                let since = job.next_run - Duration::seconds(1);
+                runner::catch_up(job, &self.defaults, since, now, &self.paths);
+            } else {
+                due.push((job.name.clone(), Trigger::Schedule, Some(job.next_run)));
+            }
+            job.get_next_run(&self.defaults);
+            if job.never_runs() {
+                add_to_job_log(
+                    LogType::Error,
+                    &job.name,
+                    None,
+                    runner::NEVER_RUNS.to_string(),
+                );
+            }
+            started = true;
+        }
+        for (name, trigger, scheduled) in due {
+            self.start_or_queue(name, trigger, scheduled);
+        }
+        if started {
+            self.jobs.sort_by_key(|job| job.next_run);
+            self.print_next_run();
+        }
+        let (due, delayed) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition(|(at, _)| *at <= now);
+        self.delayed = delayed;
+        for (_, name) in due {
+            match self.jobs.iter().find(|job| job.name == name) {
+                Some(job) if job.enabled && !self.paused => {
+                    self.start_or_queue(name, Trigger::Dependency, None)
+                }
+                // Paused, disabled or removed since its upstream jobs finished
+                _ => add_to_job_log(
+                    LogType::Info,
+                    &name,
+                    None,
+                    "Skipped the run that depended on other jobs".to_string(),
+                ),
+            }
+        }
+    }
+
+    // Start the job now, or once a run ends when `max_concurrent` runs are in progress
+    fn start_or_queue(&mut self, name: String, trigger: Trigger, scheduled: Option<DateTime<Utc>>) {
+        if !runner::has_room() {
+            add_to_job_log(
+                LogType::Info,
+                &name,
+                None,
+                format!(
+                    "Queued, {} runs are already in progress",
+                    config().max_concurrent.unwrap_or_default()
+                ),
+            );
+            self.queued.push_back((name, trigger, scheduled));
+            return;
+        }
+        if let Some(job) = self.jobs.iter().find(|job| job.name == name) {
+            runner::start(job, &self.defaults, &self.paths, trigger, scheduled, None);
+        }
+    }
+
+    fn start_queued(&mut self) {
+        while runner::has_room() {
+            let Some((name, trigger, scheduled)) = self.queued.pop_front() else {
+                return;
+            };
+            match self.jobs.iter().find(|job| job.name == name) {
+                Some(job) if job.enabled && !self.paused => {
+                    runner::start(job, &self.defaults, &self.paths, trigger, scheduled, None);
+                }
+                // Paused, disabled or removed while it waited
+                _ => add_to_job_log(
+                    LogType::Info,
+                    &name,
+                    None,
+                    "Skipped the queued run".to_string(),
+                ),
+            }
+        }
+    }
+
+    // Record the outcome for the jobs that come after `name`, and plan the ones whose upstream
+    // jobs have all finished as required in the same cycle
+    fn finished(
+        &mut self,
+        name: &str,
+        success: bool,
+        trigger: Trigger,
+        scheduled: Option<DateTime<Utc>>,
+    ) {
+        let now = Utc::now();
+        let Some(upstream) = self.jobs.iter().find(|job| job.name == name) else {
+            return;
+        };
+        let cycle = match (trigger, scheduled) {
+            (_, Some(scheduled)) => Cycle::of(upstream, &self.defaults, scheduled),
+            (Trigger::Dependency, None) => match self.cycles.get(name) {
+                Some(cycle) => *cycle,
+                None => Cycle::of(upstream, &self.defaults, now),
+            },
+            // Started by hand, it stands for the scheduled run to come
+            (_, None) => Cycle::of(upstream, &self.defaults, now),
+        };
+        for job in &self.jobs {
+            let options = job.options.inherit(&self.defaults);
+            if !options.after.iter().any(|after| after == name) {
+                continue;
+            }
+            let condition = options.on.unwrap_or(Condition::Success);
+            let outcome = (name, success, cycle);
+            if let Some(cycle) = self
+                .waiting
+                .record(&job.name, &options.after, condition, outcome)
+            {
+                self.cycles.insert(job.name.clone(), cycle);
+                let delay = Duration::seconds(options.delay.unwrap_or_default() as i64);
+                self.delayed.push((now + delay, job.name.clone()));
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) -> Reply {
+        let error = |message: String| Reply::Error { message };
+        match command {
+            Command::ListJobs => Reply::Jobs {
+                jobs: self
+                    .jobs
+                    .iter()
+                    .map(|job| JobState {
+                        name: job.name.clone(),
+                        cron: job.cron.clone(),
+                        next_run: job.next_run,
+                        paused: !job.enabled,
+                    })
+                    .collect(),
+            },
+            Command::ListRuns => Reply::Runs {
+                runs: runner::running(),
+            },
+            Command::RunNow { .. } if !runner::has_room() => error(format!(
+                "{} runs are already in progress, the most allowed at once",
+                config().max_concurrent.unwrap_or_default()
+            )),
+            // The next scheduled run stays as it was
+            Command::RunNow { job, args } => match self.jobs.iter().find(|j| j.name == job) {
+                Some(job)
+                    if args.as_ref().is_some_and(|args| !args.is_empty())
+                        && !job.takes_args(&self.defaults) =>
+                {
+                    error(format!(
+                        "{} doesn't read its arguments, its command has no $1 or $@",
+                        job.name
+                    ))
+                }
+                Some(job) => match runner::start(
+                    job,
+                    &self.defaults,
+                    &self.paths,
+                    Trigger::Manual,
+                    None,
+                    args.as_deref(),
+                ) {
+                    Some(started) => Reply::Started {
+                        run_id: started.run_id,
+                    },
+                    None => error(format!("{} couldn't be started, see its history", job.name)),
+                },
+                None => error(format!("No job named {}", job)),
+            },
+            Command::Cancel { run_id } => match runner::cancel(&run_id) {
+                true => Reply::Done,
+                false => error(format!("No run {} in progress", run_id)),
+            },
+            Command::Pause { job } => match self.set_enabled(&job, false) {
+                Ok(()) => Reply::Done,
+                Err(e) => error(e),
+            },
+            Command::Resume { job } => match self.set_enabled(&job, true) {
+                Ok(()) => Reply::Done,
+                Err(e) => error(e),
+            },
+            Command::PauseScheduler => {
+                add_to_log(LogType::Info, "Paused every job".to_string());
+                self.paused = true;
+                Reply::Done
+            }
+            Command::ResumeScheduler => {
+                add_to_log(LogType::Info, "Resumed scheduling".to_string());
+                self.paused = false;
+                Reply::Done
+            }
+            Command::Reload => {
+                self.reload();
+                Reply::Done
+            }
+            Command::Status => Reply::Status(Status {
+                pid: std::process::id(),
+                daemon_version: env!("CARGO_PKG_VERSION").to_string(),
+                started: self.started,
+                jobs: self.jobs.len(),
+                running: runner::running().len(),
+                paused: self.paused,
+            }),
+        }
+    }
+
+    // The flag is written to the file the job comes from so it survives restarts, the reload
+    // that follows finds nothing else changed
+    fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let Some(job) = self.jobs.iter_mut().find(|job| job.name == name) else {
+            return Err(format!("No job named {}", name));
+        };
+        let (mut files, errors) = JobsFiles::load(&self.paths.jobs_file, &self.paths.jobs_dir);
+        // Its file may be the one left out, writing it would drop what couldn't be read
+        if let Some(e) = errors.iter().find(|e| job.source.as_ref() == Some(&e.path)) {
+            return Err(format!("Invalid jobs file, {}", e));
+        }
+        if let Some((path, jobs_file)) = files.file_of(name) {
+            for saved in jobs_file.jobs.iter_mut().filter(|job| job.name == name) {
+                saved.enabled = enabled;
+            }
+            // The other lines of the file are left as they are
+            let original = fs::read_to_string(path)
+                .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+            let updated = update_jobs_file(&original, jobs_file)
+                .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))?;
+            fs::write(path, updated)
+                .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))?;
+        }
+        job.enabled = enabled;
+        let state = if enabled { "Resumed" } else { "Paused" };
+        add_to_job_log(LogType::Info, name, None, state.to_string());
+        Ok(())
+    }
+
+    // Only the jobs whose definition changed are replaced, the others keep their next run.
+    // Runs already started aren't affected either way. The jobs of a file left out stay as
+    // they were until it's fixed.
+    fn reload(&mut self) {
+        let (
+            JobsFile {
+                defaults, mut jobs, ..
+            },
+            left_out,
+        ) = load_jobs(&self.paths.jobs_file, &self.paths.jobs_dir);
+        for old in &self.jobs {
+            let kept = old
+                .source
+                .as_ref()
+                .is_some_and(|path| left_out.contains(path));
+            if kept && !jobs.iter().any(|job| job.name == old.name) {
+                jobs.push(old.clone());
+            }
+        }
+        let definition = |job: &Job, defaults: &JobOptions| {
+            (
+                job.cron.clone(),
+                job.command.clone(),
+                job.options.inherit(defaults),
+                job.enabled,
+            )
+        };
+        let (mut added, mut changed, mut unchanged) = (vec![], vec![], 0);
+        let mut old_jobs = std::mem::take(&mut self.jobs);
+        for job in jobs {
+            match old_jobs.iter().position(|old| old.name == job.name) {
+                Some(index) => {
+                    let old = old_jobs.remove(index);
+                    if definition(&old, &self.defaults) == definition(&job, &defaults) {
+                        unchanged += 1;
+                        self.jobs.push(old);
+                    } else {
+                        changed.push(job.name.clone());
+                        self.jobs.push(job);
+                    }
+                }
+                None => {
+                    added.push(job.name.clone());
+                    self.jobs.push(job);
+                }
+            }
+        }
+        let removed: Vec<String> = old_jobs.into_iter().map(|job| job.name).collect();
+        self.defaults = defaults;
+        self.jobs.sort_by_key(|job| job.next_run);
+        let list = |names: &[String]| match names.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", names.join(", ")),
+        };
+        add_to_log(
+            LogType::Info,
+            format!(
+                "Reloaded the jobs file: {} added{}, {} changed{}, {} removed{}, {} unchanged",
+                added.len(),
+                list(&added),
+                changed.len(),
+                list(&changed),
+                removed.len(),
+                list(&removed),
+                unchanged
+            ),
+        );
+        self.print_next_run();
+    }
+
+    // Next runs computed before the clock went back could be hours away now
+    fn clock_went_back(&mut self, seconds: i64) {
+        add_to_log(
+            LogType::Warn,
+            format!(
+                "The clock went back by {}s, computing the next runs again",
+                seconds
+            ),
+        );
+        for job in &mut self.jobs {
+            job.get_next_run(&self.defaults);
+            if job.never_runs() {
+                add_to_job_log(
+                    LogType::Error,
+                    &job.name,
+                    None,
+                    runner::NEVER_RUNS.to_string(),
+                );
+            }
+        }
+        self.jobs.sort_by_key(|job| job.next_run);
+        self.print_next_run();
+    }
+
+    fn print_next_run(&self) {
+        match self.jobs.first() {
+            // Jobs run only on demand come last
+            Some(job) if job.next_run == DateTime::<Utc>::MAX_UTC => {
+                add_to_log(LogType::Debug, "There is no scheduled run".to_string())
+            }
+            Some(job) => add_to_job_log(
+                LogType::Debug,
+                &job.name,
+                None,
+                format!("Next to run, at {}", format_time(job.next_run)),
+            ),
+            None => add_to_log(LogType::Debug, "There is no job to run".to_string()),
+        }
+    }
+}
+
+// Time a run stands for: from when it was due to the next time its job is. The outcomes of the
+// jobs a dependent job comes after only combine when their cycles overlap for most of their
+// length, so a job that didn't run in the last cycle doesn't count with its run of the one
+// before, and jobs due at different times pair with their nearest runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cycle {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl Cycle {
+    fn of(job: &Job, defaults: &JobOptions, start: DateTime<Utc>) -> Cycle {
+        let end = get_next_run_from_in(start, &job.cron, job.time_zone(defaults));
+        Cycle { start, end }
+    }
+}
+
+// Outcome and cycle of the last run of each job a dependent job comes after, by dependent job
+#[derive(Default)]
+struct Waiting {
+    outcomes: HashMap<String, HashMap<String, (bool, Cycle)>>,
+}
+
+impl Waiting {
+    // The cycle shared by the runs that start `job` once they all finished as `condition`
+    // requires, their outcomes are then forgotten
+    fn record(
+        &mut self,
+        job: &str,
+        after: &[String],
+        condition: Condition,
+        (upstream, success, cycle): (&str, bool, Cycle),
+    ) -> Option<Cycle> {
+        let outcomes = self.outcomes.entry(job.to_string()).or_default();
+        outcomes.insert(upstream.to_string(), (success, cycle));
+        let mut cycles = vec![];
+        for after in after {
+            let (success, cycle) = outcomes.get(after)?;
+            if !condition.is_met(*success) {
+                return None;
+            }
+            cycles.push(*cycle);
+        }
+        let shared = Cycle {
+            start: cycles.iter().map(|cycle| cycle.start).max()?,
+            end: cycles.iter().map(|cycle| cycle.end).min()?,
+        };
+        // Jobs never due stand for every cycle to come
+        let same = |cycle: &Cycle| {
+            cycle.end == DateTime::<Utc>::MAX_UTC
+                || (shared.end - shared.start) * 2 > cycle.end - cycle.start
+        };
+        if shared.start >= shared.end || !cycles.iter().all(same) {
+            return None;
+        }
+        self.outcomes.remove(job);
+        Some(shared)
+    }
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use utils::parse_job;
+
+    #[test]
+    fn dependency_cycles() {
+        let defaults = JobOptions::default();
+        let a = parse_job("a: 0 1 * * * a.sh".to_string()).unwrap();
+        let c = parse_job("c: 0 2 * * * c.sh".to_string()).unwrap();
+        let after = ["a".to_string(), "c".to_string()];
+        let day = |day, hour| Utc.with_ymd_and_hms(2024, 5, day, hour, 0, 0).unwrap();
+        let mut waiting = Waiting::default();
+        let mut record = |job: &Job, day, success| {
+            let cycle = Cycle::of(job, &defaults, day);
+            let outcome = (job.name.as_str(), success, cycle);
+            waiting.record("b", &after, Condition::Success, outcome)
+        };
+        // Monday
+        assert_eq!(record(&a, day(6, 1), true), None);
+        assert_eq!(record(&c, day(6, 2), false), None);
+        // Tuesday, a didn't run
+        assert_eq!(record(&c, day(7, 2), true), None);
+        // Wednesday, a goes with the run of c that follows it rather than the one before
+        assert_eq!(record(&a, day(8, 1), true), None);
+        let cycle = record(&c, day(8, 2), true).unwrap();
+        assert_eq!((cycle.start, cycle.end), (day(8, 2), day(9, 1)));
+        // Every outcome was used
+        assert_eq!(record(&c, day(8, 2), true), None);
+    }
+}
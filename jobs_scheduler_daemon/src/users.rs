@@ -0,0 +1,150 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem::MaybeUninit;
+use std::path::PathBuf;
+use std::ptr;
+
+pub struct Account {
+    pub name: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    pub home: PathBuf,
+}
+
+// Ids the job process switches to before exec, everything is resolved beforehand
+// because only async-signal-safe calls are allowed between fork and exec.
+pub struct Identity {
+    pub account: Option<Account>,
+    pub gid: libc::gid_t,
+    pub groups: Vec<libc::gid_t>,
+}
+
+impl Identity {
+    pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Identity, String> {
+        let account = user.map(find_user).transpose()?;
+        let gid = match (group, &account) {
+            (Some(group), _) => find_group(group)?,
+            (None, Some(account)) => account.gid,
+            (None, None) => unsafe { libc::getgid() },
+        };
+        let groups = match &account {
+            Some(account) => supplementary_groups(account, gid)?,
+            None => vec![gid],
+        };
+        if !is_root() {
+            let own_uid = unsafe { libc::getuid() };
+            if account.as_ref().is_some_and(|a| a.uid != own_uid) || group.is_some() {
+                return Err("only a daemon running as root can switch user or group".to_string());
+            }
+        }
+        Ok(Identity {
+            account,
+            gid,
+            groups,
+        })
+    }
+
+    // Called in the child between fork and exec, groups first as they can't be set anymore once
+    // the user is switched
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            if libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) != 0
+                || libc::setgid(self.gid) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(account) = &self.account {
+                if libc::setuid(account.uid) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+pub fn find_user(name: &str) -> Result<Account, String> {
+    let c_name = CString::new(name).map_err(|_| format!("invalid user name `{}`", name))?;
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+    let mut result: *mut libc::passwd = ptr::null_mut();
+    loop {
+        let error = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match error {
+            0 => break,
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            _ => return Err(io::Error::from_raw_os_error(error).to_string()),
+        }
+    }
+    if result.is_null() {
+        return Err(format!("user `{}` doesn't exist", name));
+    }
+    let passwd = unsafe { passwd.assume_init() };
+    Ok(Account {
+        name: name.to_owned(),
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+        home: PathBuf::from(
+            unsafe { CStr::from_ptr(passwd.pw_dir) }
+                .to_string_lossy()
+                .into_owned(),
+        ),
+    })
+}
+
+pub fn find_group(name: &str) -> Result<libc::gid_t, String> {
+    let c_name = CString::new(name).map_err(|_| format!("invalid group name `{}`", name))?;
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut group = MaybeUninit::<libc::group>::uninit();
+    let mut result: *mut libc::group = ptr::null_mut();
+    loop {
+        let error = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                group.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match error {
+            0 => break,
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            _ => return Err(io::Error::from_raw_os_error(error).to_string()),
+        }
+    }
+    if result.is_null() {
+        return Err(format!("group `{}` doesn't exist", name));
+    }
+    Ok(unsafe { group.assume_init() }.gr_gid)
+}
+
+fn supplementary_groups(account: &Account, gid: libc::gid_t) -> Result<Vec<libc::gid_t>, String> {
+    let c_name = CString::new(account.name.as_str()).unwrap_or_default();
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let found =
+            unsafe { libc::getgrouplist(c_name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if found >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        if count as usize <= groups.len() {
+            return Err(format!("couldn't list the groups of `{}`", account.name));
+        }
+        groups.resize(count as usize, 0);
+    }
+}
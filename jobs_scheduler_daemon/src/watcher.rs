@@ -0,0 +1,87 @@
+use crate::scheduler::Event;
+use crate::{add_to_log, LogType};
+use inotify::{Inotify, WatchMask};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+// Saving a file usually comes as a burst of events, they are handled as one reload
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+// Ask for a reload each time the jobs file or a file of the jobs directory is written, replaced
+// or removed. The folder of the jobs file is watched rather than the file itself, which editors
+// often replace with a new one, and the jobs directory is watched from the moment it exists.
+pub fn watch_jobs_files(jobs_file: &Path, jobs_dir: &Path, events: Sender<Event>) {
+    let (Some(folder), Some(file_name)) = (jobs_file.parent(), jobs_file.file_name()) else {
+        return;
+    };
+    let file_name = file_name.to_owned();
+    let dir_name = jobs_dir
+        .parent()
+        .filter(|parent| *parent == folder)
+        .and_then(|_| jobs_dir.file_name())
+        .map(|name| name.to_owned());
+    let jobs_dir = jobs_dir.to_path_buf();
+    let mask =
+        WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::MOVED_FROM | WatchMask::DELETE;
+    let watched = Inotify::init().and_then(|inotify| {
+        let folder_watch = inotify.watches().add(folder, mask | WatchMask::CREATE)?;
+        Ok((inotify, folder_watch))
+    });
+    let (mut inotify, folder_watch) = match watched {
+        Ok(watched) => watched,
+        Err(e) => {
+            add_to_log(
+                LogType::Error,
+                format!(
+                    "Couldn't watch the jobs file, send SIGHUP to reload it: {}",
+                    e
+                ),
+            );
+            return;
+        }
+    };
+    // Missing until someone creates it
+    let mut dir_watch = inotify.watches().add(&jobs_dir, mask).ok();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let (mut changed, mut dir_created) = (false, false);
+            match inotify.read_events_blocking(&mut buffer) {
+                Ok(read) => {
+                    for event in read {
+                        if event.wd == folder_watch {
+                            changed |= event.name == Some(file_name.as_os_str());
+                            dir_created |= dir_name.is_some() && event.name == dir_name.as_deref();
+                        } else {
+                            changed |= dir_watch.as_ref() == Some(&event.wd);
+                        }
+                    }
+                }
+                Err(e) => {
+                    add_to_log(
+                        LogType::Error,
+                        format!("Stopped watching the jobs file: {}", e),
+                    );
+                    return;
+                }
+            };
+            if dir_created {
+                dir_watch = inotify.watches().add(&jobs_dir, mask).ok();
+                changed = true;
+            }
+            if !changed {
+                continue;
+            }
+            thread::sleep(SETTLE_TIME);
+            while inotify
+                .read_events(&mut buffer)
+                .is_ok_and(|read| read.count() > 0)
+            {}
+            if events.send(Event::Reload).is_err() {
+                return;
+            }
+        }
+    });
+}
@@ -0,0 +1,314 @@
+use crate::ipc::SOCKET_NAME;
+use crate::{
+    parse_duration, parse_size, parse_time_zone, Condition, HistoryBackend, JobOptions, Retention,
+    JOBS_DIR,
+};
+use chrono_tz::Tz;
+use directories::{BaseDirs, UserDirs};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// In the XDG config directory, ~/.config/job_scheduler/config.toml by default
+pub const CONFIG_FILE: &str = "config.toml";
+const CONFIG_DIR: &str = "job_scheduler";
+pub const LOG_LEVELS: [&str; 4] = ["debug", "info", "warn", "error"];
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 << 20;
+const DEFAULT_LOG_KEEP: usize = 5;
+
+// Settings shared by the daemon and the client, everything the file leaves out keeps its default
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    // ~/job_scheduler by default, the files below are taken from there unless absolute
+    pub data_dir: PathBuf,
+    pub jobs_file: PathBuf,
+    // Each of its files adds jobs to those of the jobs file
+    pub jobs_dir: PathBuf,
+    pub history_file: PathBuf,
+    pub log_file: PathBuf,
+    // Full output of each run
+    pub runs_dir: PathBuf,
+    // Fill the options the jobs file leaves unset
+    pub shell: Option<String>,
+    pub time_zone: Option<Tz>,
+    // Runs in progress at once, the other ones wait for a slot
+    pub max_concurrent: Option<usize>,
+    pub log: LogConfig,
+    pub history_backend: HistoryBackend,
+    pub retention: Retention,
+    pub notifications: Notifications,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    // One of `LOG_LEVELS`
+    pub level: String,
+    pub stderr: bool,
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+// Command run through the shell at the end of the runs whose status matches `on`, with the
+// JOB, RUN_ID, STATUS, MESSAGE and OUTPUT variables set
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notifications {
+    pub command: Option<String>,
+    pub on: Condition,
+}
+
+// Content of the file, every value is checked once read so errors name the key at fault
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    paths: PathsSection,
+    jobs: JobsSection,
+    log: LogSection,
+    history: HistorySection,
+    notifications: NotificationsSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PathsSection {
+    data_dir: Option<String>,
+    jobs_file: Option<String>,
+    jobs_dir: Option<String>,
+    history_file: Option<String>,
+    log_file: Option<String>,
+    runs_dir: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct JobsSection {
+    shell: Option<String>,
+    time_zone: Option<String>,
+    max_concurrent: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LogSection {
+    level: Option<String>,
+    stderr: Option<bool>,
+    max_size: Option<String>,
+    keep: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct HistorySection {
+    backend: Option<String>,
+    keep_for: Option<String>,
+    keep_runs: Option<usize>,
+    keep_size: Option<String>,
+    compress_after: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct NotificationsSection {
+    command: Option<String>,
+    on: Option<String>,
+}
+
+impl Config {
+    // `path` comes from `--config` and must exist, the default file may be missing
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let home_dir = UserDirs::new()
+            .ok_or("No home directory")?
+            .home_dir()
+            .to_path_buf();
+        let (path, required) = match path {
+            Some(path) => (Some(path.to_path_buf()), true),
+            None => (default_config_file(), false),
+        };
+        let Some(path) = path else {
+            return Config::parse("", &home_dir);
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => String::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Config::parse(&content, &home_dir).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Relative paths are taken from `home_dir`
+    pub fn parse(content: &str, home_dir: &Path) -> Result<Config, String> {
+        let file: ConfigFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let path = |value: &str| match value.strip_prefix("~/") {
+            Some(rest) => home_dir.join(rest),
+            None => home_dir.join(value),
+        };
+        let paths = file.paths;
+        let data_dir = path(paths.data_dir.as_deref().unwrap_or("job_scheduler"));
+        let in_data_dir = |value: Option<String>, default: &str| match value {
+            Some(value) if value.starts_with("~/") => path(&value),
+            Some(value) => data_dir.join(value),
+            None => data_dir.join(default),
+        };
+        let history_backend = match file.history.backend.as_deref() {
+            None | Some("sqlite") => HistoryBackend::Sqlite,
+            Some("csv") => HistoryBackend::Csv,
+            Some(backend) => {
+                return Err(format!(
+                    "`history.backend` expects sqlite or csv, not `{}`",
+                    backend
+                ))
+            }
+        };
+        let jobs = file.jobs;
+        if jobs.shell.as_deref() == Some("") {
+            return Err("`jobs.shell` needs a path".to_string());
+        }
+        if jobs.max_concurrent == Some(0) {
+            return Err("`jobs.max_concurrent` must be at least 1".to_string());
+        }
+        let log = file.log;
+        let level = log.level.unwrap_or("info".to_string());
+        if !LOG_LEVELS.contains(&level.as_str()) {
+            return Err(format!(
+                "`log.level` expects debug, info, warn or error, not `{}`",
+                level
+            ));
+        }
+        let history = file.history;
+        let duration = |key: &str, value: Option<String>| {
+            value.map(|value| parse_duration(key, &value)).transpose()
+        };
+        let size = |key: &str, value: Option<String>| {
+            value.map(|value| parse_size(key, &value)).transpose()
+        };
+        let notifications = file.notifications;
+        Ok(Config {
+            jobs_file: in_data_dir(paths.jobs_file, "jobs"),
+            jobs_dir: in_data_dir(paths.jobs_dir, JOBS_DIR),
+            history_file: in_data_dir(paths.history_file, history_backend.file_name()),
+            log_file: in_data_dir(paths.log_file, "logs"),
+            runs_dir: in_data_dir(paths.runs_dir, "runs"),
+            data_dir,
+            shell: jobs.shell,
+            time_zone: jobs
+                .time_zone
+                .map(|tz| parse_time_zone("jobs.time_zone", &tz))
+                .transpose()?,
+            max_concurrent: jobs.max_concurrent,
+            log: LogConfig {
+                level,
+                stderr: log.stderr.unwrap_or(false),
+                max_size: size("log.max_size", log.max_size)?.unwrap_or(DEFAULT_LOG_MAX_SIZE),
+                keep: log.keep.unwrap_or(DEFAULT_LOG_KEEP),
+            },
+            history_backend,
+            retention: Retention {
+                max_age: duration("history.keep_for", history.keep_for)?,
+                max_runs: history.keep_runs,
+                max_size: size("history.keep_size", history.keep_size)?,
+                compress_after: duration("history.compress_after", history.compress_after)?,
+            },
+            notifications: Notifications {
+                command: notifications
+                    .command
+                    .filter(|command| !command.trim().is_empty()),
+                on: match notifications.on.as_deref() {
+                    None | Some("failure") => Condition::Failure,
+                    Some("success") => Condition::Success,
+                    Some("always") => Condition::Always,
+                    Some(on) => {
+                        return Err(format!(
+                            "`notifications.on` expects success, failure or always, not `{}`",
+                            on
+                        ))
+                    }
+                },
+            },
+        })
+    }
+
+    // Options of the config the jobs file inherits
+    pub fn job_defaults(&self) -> JobOptions {
+        JobOptions {
+            shell: self.shell.clone(),
+            tz: self.time_zone,
+            ..JobOptions::default()
+        }
+    }
+
+    pub fn socket(&self) -> PathBuf {
+        self.data_dir.join(SOCKET_NAME)
+    }
+
+    // Held by the running daemon
+    pub fn pid_file(&self) -> PathBuf {
+        self.data_dir.join("daemon.pid")
+    }
+}
+
+pub fn default_config_file() -> Option<PathBuf> {
+    BaseDirs::new().map(|dirs| dirs.config_dir().join(CONFIG_DIR).join(CONFIG_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file() {
+        let home = std::path::Path::new("/home/me");
+        let config = Config::parse("", home).unwrap();
+        assert_eq!(config.jobs_file, home.join("job_scheduler/jobs"));
+        assert_eq!(config.jobs_dir, home.join("job_scheduler/jobs.d"));
+        assert_eq!(config.history_file, home.join("job_scheduler/history.db"));
+        assert_eq!(config.socket(), home.join("job_scheduler/daemon.sock"));
+        assert_eq!(config.log.level, "info");
+        assert!(config.retention.is_empty() && config.notifications.command.is_none());
+
+        let config = Config::parse(
+            r#"
+            [paths]
+            data_dir = "/srv/jobs"
+            jobs_file = "~/my_jobs"
+            [jobs]
+            shell = "/bin/bash"
+            time_zone = "Europe/Paris"
+            max_concurrent = 2
+            [log]
+            level = "debug"
+            max_size = "1M"
+            [history]
+            backend = "csv"
+            keep_for = "30d"
+            keep_runs = 100
+            [notifications]
+            command = "notify-send $JOB"
+            on = "always"
+            "#,
+            home,
+        )
+        .unwrap();
+        assert_eq!(config.jobs_file, home.join("my_jobs"));
+        assert_eq!(
+            config.history_file,
+            std::path::Path::new("/srv/jobs/history")
+        );
+        assert_eq!(config.history_backend, HistoryBackend::Csv);
+        assert_eq!(
+            config.job_defaults().to_string(),
+            "shell=/bin/bash tz=Europe/Paris"
+        );
+        assert_eq!(config.max_concurrent, Some(2));
+        assert_eq!(config.log.max_size, 1 << 20);
+        assert_eq!(config.retention.max_age, Some(30 * 24 * 60 * 60));
+        assert_eq!(config.notifications.on, Condition::Always);
+
+        let error = |content: &str| Config::parse(content, home).unwrap_err();
+        assert!(error("[history]\nkeep_for = \"soon\"").starts_with("`history.keep_for`"));
+        assert!(error("[jobs]\ntime_zone = \"Nowhere\"").starts_with("`jobs.time_zone`"));
+        assert!(error("[jobs]\nmax_concurrent = 0").starts_with("`jobs.max_concurrent`"));
+        assert!(error("[log]\nlevel = \"loud\"").starts_with("`log.level`"));
+        assert!(error("[jobs]\nshel = \"/bin/sh\"").contains("line 2"));
+        assert!(error("[log]\nkeep = \"5\"").contains("line 2"));
+    }
+}
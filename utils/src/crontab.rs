@@ -0,0 +1,443 @@
+use crate::options::{self, format_env_line, parse_env_line};
+use crate::{parse_job_line, parse_time_zone, Job, JobOptions, JobsFile, Mode, ParseError, MANUAL};
+use chrono_tz::Tz;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+// Comments `export_crontab` writes so that importing its output gives the same jobs back
+const JOB_COMMENT: &str = "# job:";
+const DEFAULTS_COMMENT: &str = "# defaults:";
+
+const NICKNAMES: [(&str, &str); 8] = [
+    ("@hourly", "0 * * * *"),
+    ("@daily", "0 0 * * *"),
+    ("@midnight", "0 0 * * *"),
+    // Monday is 0 here, see `FIELDS`
+    ("@weekly", "0 0 * * 6"),
+    ("@monthly", "0 0 1 * *"),
+    ("@yearly", "0 0 1 1 *"),
+    ("@annually", "0 0 1 1 *"),
+    (MANUAL, MANUAL),
+];
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// Range of each field in cron, whose days of the week count from Sunday, 0 or 7, when they count
+// from Monday here
+const FIELDS: [(u32, u32); 5] = [(0, 59), (0, 23), (1, 31), (1, 12), (0, 7)];
+
+// Jobs of a crontab, `system` when its lines have a user column like /etc/crontab and the files
+// of /etc/cron.d. A job is named by the `# job: name [settings]` comment right above its line,
+// as `export_crontab` writes them, or else after its command, never as one of `taken`.
+// SHELL sets the shell of the jobs, CRON_TZ the time zone of the lines below it, and the other
+// variables, MAILTO included, are set for every job. The daemon mails nothing itself, see the
+// notifications of the config.
+pub fn import_crontab(
+    content: &str,
+    system: bool,
+    taken: &[String],
+) -> Result<JobsFile, ParseError> {
+    let mut jobs_file = JobsFile::default();
+    let mut names = taken.to_vec();
+    let mut time_zone: Option<Tz> = None;
+    // The job of the `# job:` comment just read, its line comes next
+    let mut pending: Option<Job> = None;
+    for (index, line) in content.lines().enumerate() {
+        let to_error = |message| ParseError {
+            line: index + 1,
+            message,
+        };
+        let line = line.trim();
+        let header = pending.take();
+        if let Some(rest) = line.strip_prefix(JOB_COMMENT) {
+            let job = parse_job_line(&format!("{}: {} true", rest.trim(), MANUAL))
+                .map_err(to_error)?
+                .ok_or(to_error("expected `# job: name [settings]`".to_string()))?;
+            pending = Some(job);
+        } else if let Some(rest) = line.strip_prefix(DEFAULTS_COMMENT) {
+            let defaults = JobOptions::parse(rest).map_err(to_error)?;
+            jobs_file.defaults = defaults.inherit(&jobs_file.defaults);
+        } else if let Some(commented) = line.strip_prefix('#') {
+            // A line commented out below a `# job:` comment is a disabled or `@manual` job
+            if let Some(header) = header {
+                let (cron, user, command) = parse_entry(commented, system).map_err(to_error)?;
+                let mut job = new_job(header, cron, user, command, &mut names);
+                job.enabled = job.enabled && job.cron == MANUAL;
+                jobs_file.jobs.push(job);
+            }
+        } else if let Some((name, value)) = parse_env_line(line) {
+            match name.as_str() {
+                "SHELL" => jobs_file.defaults.shell = Some(value),
+                "CRON_TZ" => {
+                    time_zone = Some(parse_time_zone("CRON_TZ", &value).map_err(to_error)?)
+                }
+                _ => options::set_env(&mut jobs_file.defaults.env, &name, &value),
+            }
+        } else if !line.is_empty() {
+            let (cron, user, command) = parse_entry(line, system).map_err(to_error)?;
+            let header =
+                header.unwrap_or(Job::new(String::new(), MANUAL.to_string(), command.clone()));
+            let mut job = new_job(header, cron, user, command, &mut names);
+            job.options.tz = job.options.tz.or(time_zone);
+            jobs_file.jobs.push(job);
+        }
+    }
+    for job in &mut jobs_file.jobs {
+        job.get_next_run(&jobs_file.defaults);
+    }
+    Ok(jobs_file)
+}
+
+// The `header` job with the schedule and command of the line, named after the command when the
+// header gave no name or one already taken
+fn new_job(
+    header: Job,
+    cron: String,
+    user: Option<String>,
+    command: String,
+    names: &mut Vec<String>,
+) -> Job {
+    let base = match header.name.is_empty() {
+        true => command_name(&command),
+        false => header.name.clone(),
+    };
+    let mut name = base.clone();
+    for suffix in 2.. {
+        if !names.contains(&name) {
+            break;
+        }
+        name = format!("{}-{}", base, letters(suffix));
+    }
+    names.push(name.clone());
+    let mut options = header.options;
+    options.user = options.user.or(user);
+    Job {
+        name,
+        cron,
+        command,
+        options,
+        ..header
+    }
+}
+
+// Letters of the program the command runs, names may have nothing else. Variables set for it
+// and a `cd dir &&` in front are passed over.
+fn command_name(command: &str) -> String {
+    let words = shell_words::split(command).unwrap_or_default();
+    let mut words = words.iter().map(String::as_str).peekable();
+    let mut program = "";
+    while let Some(word) = words.next() {
+        match word {
+            "cd" => {
+                words.next();
+                words.next_if(|word| matches!(*word, "&&" | ";"));
+            }
+            _ if parse_env_line(word).is_some() => {}
+            _ => {
+                program = word;
+                break;
+            }
+        }
+    }
+    let stem = Path::new(program)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let name: String = stem
+        .chars()
+        .map(|c| match c.is_ascii_alphabetic() || c == '_' {
+            true => c,
+            false => '-',
+        })
+        .collect();
+    let name: Vec<&str> = name.split('-').filter(|part| !part.is_empty()).collect();
+    match name.is_empty() {
+        true => "job".to_string(),
+        false => name.join("-"),
+    }
+}
+
+// b, c... z, aa, ab... for the 2nd, 3rd... job of a name
+fn letters(mut number: usize) -> String {
+    let mut letters = vec![];
+    while number > 0 {
+        number -= 1;
+        letters.push(b'a' + (number % 26) as u8);
+        number /= 26;
+    }
+    letters.iter().rev().map(|&letter| letter as char).collect()
+}
+
+// Schedule, user and command of a crontab line
+fn parse_entry(line: &str, system: bool) -> Result<(String, Option<String>, String), String> {
+    let mut rest = line.trim();
+    let first = next_word(&mut rest).ok_or("expected a schedule and a command")?;
+    let cron = if first.starts_with('@') {
+        NICKNAMES
+            .iter()
+            .find(|(nickname, _)| nickname.eq_ignore_ascii_case(first))
+            .map(|(_, cron)| cron.to_string())
+            .ok_or(format!(
+                "`{}` has no equivalent, jobs run at set times or by hand",
+                first
+            ))?
+    } else {
+        let mut fields = vec![first];
+        for _ in 1..FIELDS.len() {
+            fields.push(next_word(&mut rest).ok_or("expected five schedule fields")?);
+        }
+        convert_schedule(&fields)?
+    };
+    let user = match system {
+        true => Some(next_word(&mut rest).ok_or("expected a user")?.to_string()),
+        false => None,
+    };
+    let command = unescape_command(rest.trim())?;
+    if command.is_empty() {
+        return Err("expected a command".to_string());
+    }
+    // Checked like a job line, a name is needed there
+    parse_job_line(&format!("job: {} {}", cron, command))?;
+    Ok((cron, user, command))
+}
+
+fn next_word<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let trimmed = rest.trim_start();
+    if trimmed.is_empty() {
+        return None;
+    }
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let (word, after) = trimmed.split_at(end);
+    *rest = after;
+    Some(word)
+}
+
+// Cron sends what follows a `%` to the input of the command, `\%` is a plain one
+fn unescape_command(command: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'%') => unescaped.push(chars.next().unwrap()),
+            '%' => return Err("`%` gives input to the command, which jobs can't do".to_string()),
+            _ => unescaped.push(c),
+        }
+    }
+    Ok(unescaped)
+}
+
+// Fields in the forms the schedules here take, days of the week moved to count from Monday
+fn convert_schedule(fields: &[&str]) -> Result<String, String> {
+    let mut converted = vec![];
+    for (index, field) in fields.iter().enumerate() {
+        let (min, max) = FIELDS[index];
+        let values = expand(field, min, max, index)
+            .ok_or(format!("`{}` isn't a schedule field cron takes", field))?;
+        let converted_field = match index {
+            4 if *field == "*" => field.to_string(),
+            4 => {
+                let days = values.iter().map(|&day| (day + 6) % 7).collect();
+                compact(&days, 0, 6)
+            }
+            _ if is_plain(field) => field.to_string(),
+            _ => compact(&values, min, max),
+        };
+        converted.push(converted_field);
+    }
+    Ok(converted.join(" "))
+}
+
+// Values of a cron field: lists of values, ranges and steps, with the names of months and days
+fn expand(field: &str, min: u32, max: u32, index: usize) -> Option<BTreeSet<u32>> {
+    let value = |value: &str| -> Option<u32> {
+        let names: &[&str] = match index {
+            3 => &MONTHS,
+            4 => &DAYS,
+            _ => &[],
+        };
+        let offset = if index == 3 { 1 } else { 0 };
+        value.parse().ok().or_else(|| {
+            names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(value))
+                .map(|position| position as u32 + offset)
+        })
+    };
+    let mut values = BTreeSet::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<usize>().ok()?)),
+            None => (part, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end || start < min || end > max || step == Some(0) {
+            return None;
+        }
+        values.extend((start..=end).step_by(step.unwrap_or(1)));
+    }
+    if index == 4 && values.remove(&7) {
+        values.insert(0);
+    }
+    Some(values)
+}
+
+// Forms that mean the same here as in cron
+fn is_plain(field: &str) -> bool {
+    let numbers = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    match field.split_once(['-', '/']) {
+        _ if field == "*" => true,
+        Some(("*", step)) => field.contains('/') && numbers(step),
+        Some((start, end)) => field.contains('-') && numbers(start) && numbers(end),
+        None => field.split(',').all(numbers),
+    }
+}
+
+// `*` for every value, a range for values in a row, a list otherwise
+fn compact(values: &BTreeSet<u32>, min: u32, max: u32) -> String {
+    let (Some(&first), Some(&last)) = (values.first(), values.last()) else {
+        return "*".to_string();
+    };
+    if first == min && last == max && values.len() as u32 == max - min + 1 {
+        "*".to_string()
+    } else if values.len() > 1 && values.len() as u32 == last - first + 1 {
+        format!("{}-{}", first, last)
+    } else {
+        let values: Vec<String> = values.iter().map(u32::to_string).collect();
+        values.join(",")
+    }
+}
+
+// The jobs as crontab lines, with a user column when `system`. What cron has no use for stays in
+// the `# job:` and `# defaults:` comments so `import_crontab` gives the same jobs back. Disabled
+// and `@manual` jobs are commented out.
+pub fn export_crontab(jobs_file: &JobsFile, system: bool) -> String {
+    let defaults = &jobs_file.defaults;
+    let mut lines = vec![];
+    if let Some(shell) = &defaults.shell {
+        lines.push(format_env_line("SHELL", shell));
+    }
+    lines.extend(
+        defaults
+            .env
+            .iter()
+            .map(|(name, value)| format_env_line(name, value)),
+    );
+    // Set along with the shell when it's read
+    let others = JobOptions {
+        mode: defaults.mode.filter(|mode| *mode != Mode::Shell),
+        shell: None,
+        env: vec![],
+        ..defaults.clone()
+    };
+    if !others.is_empty() {
+        lines.push(format!("{} {}", DEFAULTS_COMMENT, others));
+    }
+    let mut time_zone = None;
+    for job in &jobs_file.jobs {
+        let job_time_zone = job.options.tz.or(defaults.tz);
+        if job_time_zone != time_zone && job.cron != MANUAL {
+            let name = job_time_zone.unwrap_or(Tz::UTC).name();
+            lines.push(format_env_line("CRON_TZ", name));
+            time_zone = job_time_zone;
+        }
+        match job.settings().as_str() {
+            "" => lines.push(format!("{} {}", JOB_COMMENT, job.name)),
+            settings => lines.push(format!("{} {} [{}]", JOB_COMMENT, job.name, settings)),
+        }
+        let mut words = vec![export_schedule(&job.cron)];
+        if system {
+            let user = job.options.user.as_ref().or(defaults.user.as_ref());
+            words.push(user.map_or("root", |user| user.as_str()).to_string());
+        }
+        words.push(job.command.replace('%', "\\%"));
+        let line = words.join(" ");
+        match job.enabled && job.cron != MANUAL {
+            true => lines.push(line),
+            false => lines.push(format!("# {}", line)),
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+// Days of the week moved back to count from Sunday
+fn export_schedule(cron: &str) -> String {
+    let mut fields: Vec<String> = cron.split(' ').map(str::to_string).collect();
+    if cron == MANUAL || fields.len() != FIELDS.len() || fields[4] == "*" {
+        return cron.to_string();
+    }
+    let days = expand(&fields[4], 0, 6, 0).unwrap_or_default();
+    fields[4] = compact(&days.iter().map(|&day| (day + 1) % 7).collect(), 0, 6);
+    fields.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_job;
+
+    #[test]
+    fn crontabs() {
+        let crontab = "\
+# m h  dom mon dow   command
+MAILTO=ops@example.com
+SHELL=/bin/bash
+*/15 * * * * /usr/local/bin/sync.sh --quiet
+0 6 * * mon-fri backup.sh \\%Y
+# job: report [description=Weekly tags=mail]
+@weekly report.sh
+30 2 1 jan,jul * backup.sh --full
+";
+        let jobs_file = import_crontab(crontab, false, &["sync".to_string()]).unwrap();
+        let lines: Vec<String> = jobs_file.jobs.iter().map(Job::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "sync-b: */15 * * * * /usr/local/bin/sync.sh --quiet",
+                "backup: 0 6 * * 0-4 backup.sh %Y",
+                "report [description=Weekly tags=mail]: 0 0 * * 6 report.sh",
+                "backup-b: 30 2 1 1,7 * backup.sh --full",
+            ]
+        );
+        assert_eq!(jobs_file.defaults.shell.as_deref(), Some("/bin/bash"));
+        assert_eq!(jobs_file.defaults.env[0].0, "MAILTO");
+
+        let system = import_crontab(
+            "CRON_TZ=Europe/Paris\n0 3 * * 0 root backup.sh\n",
+            true,
+            &[],
+        )
+        .unwrap();
+        let job = &system.jobs[0];
+        assert_eq!(
+            job.to_string(),
+            "backup [user=root tz=Europe/Paris]: 0 3 * * 6 backup.sh"
+        );
+
+        // Exporting then importing gives the same jobs back
+        let mut jobs_file = jobs_file;
+        jobs_file.jobs[1].enabled = false;
+        jobs_file
+            .jobs
+            .push(parse_job("later: @manual later.sh".to_string()).unwrap());
+        let exported = export_crontab(&jobs_file, false);
+        assert!(exported.contains("# 0 6 * * 1-5 backup.sh \\%Y\n"));
+        let reimported = import_crontab(&exported, false, &[]).unwrap();
+        let again: Vec<String> = reimported.jobs.iter().map(Job::to_string).collect();
+        let before: Vec<String> = jobs_file.jobs.iter().map(Job::to_string).collect();
+        assert_eq!(again, before);
+        assert_eq!(reimported.defaults.env, jobs_file.defaults.env);
+
+        let error = |content: &str| import_crontab(content, false, &[]).unwrap_err();
+        assert_eq!(error("\n@reboot start.sh").line, 2);
+        assert!(error("* * * * * echo 50%").message.contains("`%`"));
+        assert!(error("* * * * 9 ls").message.contains("`9`"));
+    }
+}
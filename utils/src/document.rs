@@ -0,0 +1,298 @@
+use crate::structured::{self, read_jobs_as};
+use crate::{parse_job_line, Job, JobsFile, JobsFormat};
+use std::fmt;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
+
+// A line file as it was read. Saving jobs only writes the lines of the jobs that changed, the
+// comments, blank lines, defaults and lines that aren't jobs stay byte for byte.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobsDocument {
+    // Each with its line ending, the last one may have none
+    lines: Vec<String>,
+}
+
+impl JobsDocument {
+    pub fn parse(content: &str) -> JobsDocument {
+        JobsDocument {
+            lines: content.split_inclusive('\n').map(str::to_string).collect(),
+        }
+    }
+
+    // A job keeps its line, rewritten only when the job changed. A renamed job keeps it too,
+    // the lines of removed jobs go and new jobs come last.
+    pub fn update(&mut self, jobs: &[Job]) {
+        let written: Vec<Option<Job>> = self.lines.iter().map(|line| read_job(line)).collect();
+        let mut matching = Matching::new(written.iter().flatten(), jobs);
+        let mut removed = vec![];
+        for (index, written) in written.iter().enumerate() {
+            let Some(written) = written else {
+                continue;
+            };
+            match matching.job_of(written) {
+                Some(job) if job.to_string() != written.to_string() => self.set_line(index, job),
+                Some(_) => {}
+                None => removed.push(index),
+            }
+        }
+        for index in removed.into_iter().rev() {
+            self.lines.remove(index);
+        }
+        let ending = self.line_ending();
+        for job in matching.new_jobs {
+            // Like the file, the last line ends with a line ending or doesn't
+            match self.lines.last_mut() {
+                Some(last) if !last.ends_with('\n') => {
+                    last.push_str(ending);
+                    self.lines.push(job.to_string());
+                }
+                _ => self.lines.push(format!("{}{}", job, ending)),
+            }
+        }
+    }
+
+    fn set_line(&mut self, index: usize, job: &Job) {
+        let line = &self.lines[index];
+        let ending = &line[line.trim_end_matches(['\r', '\n']).len()..];
+        self.lines[index] = format!("{}{}", job, ending);
+    }
+
+    // The one of the first line, so files written on Windows keep theirs
+    fn line_ending(&self) -> &'static str {
+        match self.lines.first() {
+            Some(line) if line.ends_with("\r\n") => "\r\n",
+            _ => "\n",
+        }
+    }
+}
+
+impl fmt::Display for JobsDocument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.lines.iter().try_for_each(|line| f.write_str(line))
+    }
+}
+
+// The job each written one became, by name or for a renamed one by schedule and command
+struct Matching<'a> {
+    jobs: &'a [Job],
+    // Not written under their name, in order
+    new_jobs: Vec<&'a Job>,
+}
+
+impl<'a> Matching<'a> {
+    fn new<'b>(written: impl Iterator<Item = &'b Job> + Clone, jobs: &'a [Job]) -> Matching<'a> {
+        let new_jobs = jobs
+            .iter()
+            .filter(|job| !written.clone().any(|written| written.name == job.name))
+            .collect();
+        Matching { jobs, new_jobs }
+    }
+
+    // `None` for a removed job. A new job is taken for a renamed one when only its name differs,
+    // any other one is added and the written one removed.
+    fn job_of(&mut self, written: &Job) -> Option<&'a Job> {
+        if let Some(job) = self.jobs.iter().find(|job| job.name == written.name) {
+            return Some(job);
+        }
+        let renamed = self.new_jobs.iter().position(|job| {
+            job.cron == written.cron
+                && job.command == written.command
+                && job.settings() == written.settings()
+        })?;
+        Some(self.new_jobs.remove(renamed))
+    }
+}
+
+fn read_job(line: &str) -> Option<Job> {
+    parse_job_line(line.trim_end_matches(['\r', '\n']))
+        .ok()
+        .flatten()
+}
+
+// The `[[job]]` tables of the jobs that changed are updated key by key, the comments and the
+// other tables stay as they are. `None` when the document isn't one `JobsFile` can write back.
+fn update_toml(original: &str, jobs_file: &JobsFile) -> Option<String> {
+    let mut document: DocumentMut = original.parse().ok()?;
+    let (written, _) = read_jobs_as(original, JobsFormat::Toml).ok()?;
+    let fresh: DocumentMut = structured::to_string(jobs_file).ok()?.parse().ok()?;
+    if written.defaults != jobs_file.defaults {
+        match fresh.get("defaults") {
+            Some(defaults) => document["defaults"] = defaults.clone(),
+            None => {
+                document.remove("defaults");
+            }
+        }
+    }
+    let fresh_tables = match fresh.get("job") {
+        Some(Item::ArrayOfTables(tables)) => tables.clone(),
+        _ => ArrayOfTables::new(),
+    };
+    let tables = document
+        .entry("job")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()?;
+    let mut matching = Matching::new(written.jobs.iter(), &jobs_file.jobs);
+    let mut removed = vec![];
+    for (index, written) in written.jobs.iter().enumerate() {
+        let Some(job) = matching.job_of(written) else {
+            removed.push(index);
+            continue;
+        };
+        if job.to_string() != written.to_string() {
+            let new = jobs_file
+                .jobs
+                .iter()
+                .position(|other| other.name == job.name)?;
+            update_table(tables.get_mut(index)?, fresh_tables.get(new)?);
+        }
+    }
+    for index in removed.into_iter().rev() {
+        tables.remove(index);
+    }
+    for job in matching.new_jobs {
+        let new = jobs_file
+            .jobs
+            .iter()
+            .position(|other| other.name == job.name)?;
+        let mut table = fresh_tables.get(new)?.clone();
+        place(&mut table, usize::MAX);
+        tables.push(table);
+    }
+    Some(document.to_string())
+}
+
+// Keys whose value didn't change keep their place and comments
+fn update_table(table: &mut Table, fresh: &Table) {
+    let position = table.position().unwrap_or(usize::MAX);
+    table.retain(|key, _| fresh.contains_key(key));
+    for (key, item) in fresh.iter() {
+        let same = table
+            .get(key)
+            .is_some_and(|old| old.to_string().trim() == item.to_string().trim());
+        if same {
+            continue;
+        }
+        match (
+            table.get_mut(key).and_then(Item::as_value_mut),
+            item.as_value(),
+        ) {
+            (Some(old), Some(new)) => {
+                let decor = old.decor().clone();
+                *old = new.clone();
+                *old.decor_mut() = decor;
+            }
+            _ => {
+                let mut item = item.clone();
+                if let Some(table) = item.as_table_mut() {
+                    place(table, position);
+                }
+                table.insert(key, item);
+            }
+        }
+    }
+}
+
+// Tables taken from another document are written at `position` of this one, their own tables
+// right after them
+fn place(table: &mut Table, position: usize) {
+    table.set_position(position);
+    for (_, item) in table.iter_mut() {
+        if let Some(table) = item.as_table_mut() {
+            place(table, position);
+        }
+    }
+}
+
+// What to write over `original`, the content `jobs_file` was read from, to save it. Line and
+// TOML files keep everything but the jobs that changed. YAML ones are written anew, refused
+// when that would lose their comments.
+pub fn update_jobs_file(original: &str, jobs_file: &JobsFile) -> Result<String, String> {
+    if original.trim().is_empty() {
+        return Ok(jobs_file.to_string());
+    }
+    match jobs_file.format {
+        JobsFormat::Lines => {
+            let mut document = JobsDocument::parse(original);
+            document.update(&jobs_file.jobs);
+            Ok(document.to_string())
+        }
+        JobsFormat::Toml => {
+            Ok(update_toml(original, jobs_file).unwrap_or_else(|| jobs_file.to_string()))
+        }
+        JobsFormat::Yaml => {
+            let commented = original
+                .lines()
+                .any(|line| line.trim_start().starts_with('#') || line.contains(" #"));
+            match commented {
+                true => Err("its comments would be lost, YAML files are written anew".to_string()),
+                false => Ok(jobs_file.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_job, parse_jobs, parse_jobs_file, Job};
+    use std::path::Path;
+
+    #[test]
+    fn jobs_document() {
+        let content = "# Nightly jobs\r\n[timeout=1h]\r\nbackup:   0 2 * * * backup.sh\r\n\r\n\
+            # typo below\r\nreport 0 3 * * * report.sh\r\nclean: 0 4 * * * clean.sh\r\n";
+        let jobs_file = parse_jobs(content.to_string()).unwrap();
+        // Nothing changed, nothing is rewritten
+        assert_eq!(update_jobs_file(content, &jobs_file).unwrap(), content);
+
+        let mut jobs = jobs_file.jobs.clone();
+        jobs[0].enabled = false;
+        jobs[1].name = "cleanup".to_string();
+        jobs.push(parse_job("sync: */5 * * * * sync.sh".to_string()).unwrap());
+        let mut document = JobsDocument::parse(content);
+        document.update(&jobs);
+        assert_eq!(
+            document.to_string(),
+            "# Nightly jobs\r\n[timeout=1h]\r\nbackup [enabled=false]: 0 2 * * * backup.sh\r\n\r\n\
+            # typo below\r\nreport 0 3 * * * report.sh\r\ncleanup: 0 4 * * * clean.sh\r\n\
+            sync: */5 * * * * sync.sh\r\n"
+        );
+
+        // A renamed job keeps its line, others follow the file on its last line ending
+        let mut document = JobsDocument::parse("a: @manual a.sh\n# end");
+        document.update(&[parse_job("b: @manual a.sh".to_string()).unwrap()]);
+        assert_eq!(document.to_string(), "b: @manual a.sh\n# end");
+        document.update(&[parse_job("c: @manual c.sh".to_string()).unwrap()]);
+        assert_eq!(document.to_string(), "# end\nc: @manual c.sh");
+
+        let content = "# Nightly\n[defaults]\ntimeout = \"1h\" # for all\n\n\
+            [[job]]\nname = \"backup\"\ncron = \"0 2 * * *\"\ncommand = \"backup.sh\" # full\n\n\
+            # weekly\n[[job]]\nname = \"report\"\ncron = \"0 3 * * 1\"\ncommand = \"report.sh\"\n";
+        let path = Path::new("jobs.toml");
+        let jobs_file = parse_jobs_file(path, content.to_string()).unwrap();
+        assert_eq!(update_jobs_file(content, &jobs_file).unwrap(), content);
+        let mut changed = jobs_file.clone();
+        changed.jobs[0].enabled = false;
+        changed.jobs.remove(1);
+        changed
+            .jobs
+            .push(parse_job("sync: */5 * * * * sync.sh".to_string()).unwrap());
+        let updated = update_jobs_file(content, &changed).unwrap();
+        assert_eq!(
+            updated,
+            "# Nightly\n[defaults]\ntimeout = \"1h\" # for all\n\n\
+            [[job]]\nname = \"backup\"\ncron = \"0 2 * * *\"\ncommand = \"backup.sh\" # full\n\
+            enabled = false\n\n[[job]]\nname = \"sync\"\ncron = \"*/5 * * * *\"\ncommand = \"sync.sh\"\n"
+        );
+        let names = |jobs_file: &JobsFile| -> Vec<String> {
+            jobs_file.jobs.iter().map(Job::to_string).collect()
+        };
+        assert_eq!(
+            names(&parse_jobs_file(path, updated).unwrap()),
+            names(&changed)
+        );
+
+        let content = "# Nightly\njob:\n- name: backup\n  cron: 0 2 * * *\n  command: backup.sh\n";
+        let jobs_file = parse_jobs_file(Path::new("jobs.yaml"), content.to_string()).unwrap();
+        assert!(update_jobs_file(content, &jobs_file).is_err());
+    }
+}
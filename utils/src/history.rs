@@ -0,0 +1,335 @@
+use crate::ParseError;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use core::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+// First field of every history line. Lines written before it was added have the job name there,
+// which never starts with a digit.
+pub const HISTORY_VERSION: u32 = 2;
+
+pub type History = Vec<HistoryRecord>;
+
+// One finished run, as written to the history file
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRecord {
+    pub run_id: String,
+    pub name: String,
+    // When the run was due, `None` for the runs that weren't scheduled
+    pub scheduled: Option<DateTime<Utc>>,
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    // `None` when the job was killed by a signal or never started
+    pub exit_code: Option<i32>,
+    pub status: RunStatus,
+    pub trigger: Trigger,
+    // Starts at 1
+    pub attempt: u32,
+    // Full output of the run, `message` only keeps its tail
+    pub output: Option<PathBuf>,
+    // Why the run failed, or the end of its output
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RunStatus {
+    Success,
+    // Failed to start or exited with an error
+    Error,
+    // Stopped by the daemon: cancelled, over a limit or shut down
+    Killed,
+    Timeout,
+}
+
+impl fmt::Display for RunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunStatus::Success => write!(f, "SUCCESS"),
+            RunStatus::Error => write!(f, "ERROR"),
+            RunStatus::Killed => write!(f, "KILLED"),
+            RunStatus::Timeout => write!(f, "TIMEOUT"),
+        }
+    }
+}
+
+impl RunStatus {
+    pub(crate) fn parse(value: &str) -> Option<RunStatus> {
+        match value {
+            "SUCCESS" => Some(RunStatus::Success),
+            "ERROR" => Some(RunStatus::Error),
+            "KILLED" => Some(RunStatus::Killed),
+            "TIMEOUT" => Some(RunStatus::Timeout),
+            _ => None,
+        }
+    }
+}
+
+// What started a run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Schedule,
+    // Replaying a run missed while the daemon couldn't start it
+    CatchUp,
+    // Asked for from the client or the control socket
+    Manual,
+    // Started by the end of the jobs it comes after
+    Dependency,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Trigger::Schedule => write!(f, "schedule"),
+            Trigger::CatchUp => write!(f, "catch_up"),
+            Trigger::Manual => write!(f, "manual"),
+            Trigger::Dependency => write!(f, "dependency"),
+        }
+    }
+}
+
+impl Trigger {
+    pub(crate) fn parse(value: &str) -> Option<Trigger> {
+        match value {
+            "schedule" => Some(Trigger::Schedule),
+            "catch_up" => Some(Trigger::CatchUp),
+            "manual" => Some(Trigger::Manual),
+            "dependency" => Some(Trigger::Dependency),
+            _ => None,
+        }
+    }
+}
+
+impl HistoryRecord {
+    pub(crate) fn fields(&self) -> Vec<String> {
+        let millis = |time: DateTime<Utc>| time.timestamp_millis().to_string();
+        vec![
+            HISTORY_VERSION.to_string(),
+            self.run_id.clone(),
+            self.name.clone(),
+            self.scheduled.map(millis).unwrap_or_default(),
+            millis(self.started),
+            millis(self.ended),
+            self.exit_code
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+            self.status.to_string(),
+            self.trigger.to_string(),
+            self.attempt.to_string(),
+            self.output
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            self.message.clone(),
+        ]
+    }
+
+    pub(crate) fn from_fields(fields: &csv::StringRecord) -> Result<HistoryRecord, String> {
+        let field = |index: usize| fields.get(index).unwrap_or_default();
+        let version = field(0);
+        match version.parse::<u32>() {
+            Ok(HISTORY_VERSION) => {}
+            Ok(_) => return Err(format!("unknown history version {}", version)),
+            Err(_) => return HistoryRecord::from_first_version(fields),
+        }
+        if fields.len() != 12 {
+            return Err(format!("expected 12 fields, found {}", fields.len()));
+        }
+        let optional = |index: usize| Some(field(index)).filter(|value| !value.is_empty());
+        Ok(HistoryRecord {
+            run_id: field(1).to_owned(),
+            name: field(2).to_owned(),
+            scheduled: optional(3).map(parse_millis).transpose()?,
+            started: parse_millis(field(4))?,
+            ended: parse_millis(field(5))?,
+            exit_code: optional(6)
+                .map(|code| {
+                    code.parse()
+                        .map_err(|_| format!("invalid exit code {}", code))
+                })
+                .transpose()?,
+            status: RunStatus::parse(field(7))
+                .ok_or_else(|| format!("invalid status {}", field(7)))?,
+            trigger: Trigger::parse(field(8))
+                .ok_or_else(|| format!("invalid trigger {}", field(8)))?,
+            attempt: field(9)
+                .parse()
+                .map_err(|_| format!("invalid attempt {}", field(9)))?,
+            output: optional(10).map(PathBuf::from),
+            message: field(11).to_owned(),
+        })
+    }
+
+    // `name,end,status,message[,output[,trigger]]`. The first daemon wrote the end as
+    // `2024-05-01 01:02:00 UTC`, the status as `Ok` or `Error` and the message unquoted, later ones
+    // the end in milliseconds, the status names and the last two fields.
+    fn from_first_version(fields: &csv::StringRecord) -> Result<HistoryRecord, String> {
+        if fields.len() < 4 {
+            return Err(format!(
+                "expected at least 4 fields, found {}",
+                fields.len()
+            ));
+        }
+        let (ended, status, message, output, trigger) =
+            match NaiveDateTime::parse_from_str(&fields[1], "%Y-%m-%d %H:%M:%S%.f UTC") {
+                Ok(ended) => {
+                    let status = match &fields[2] {
+                        "Ok" => RunStatus::Success,
+                        "Error" => RunStatus::Error,
+                        status => return Err(format!("invalid status {}", status)),
+                    };
+                    // Commas in the message split it into several fields
+                    let message = fields.iter().skip(3).collect::<Vec<_>>().join(",");
+                    (ended.and_utc(), status, message, None, Trigger::Schedule)
+                }
+                Err(_) => (
+                    parse_millis(&fields[1])?,
+                    RunStatus::parse(&fields[2])
+                        .ok_or_else(|| format!("invalid status {}", &fields[2]))?,
+                    fields[3].to_owned(),
+                    fields.get(4).filter(|p| !p.is_empty()).map(PathBuf::from),
+                    fields
+                        .get(5)
+                        .and_then(Trigger::parse)
+                        .unwrap_or(Trigger::Schedule),
+                ),
+            };
+        // The output was named after the run ID when there was one
+        let run_id = match output.as_ref().and_then(|output| output.file_stem()) {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => ended.format("%Y%m%d-%H%M%S-%3f").to_string(),
+        };
+        Ok(HistoryRecord {
+            run_id,
+            name: fields[0].to_owned(),
+            scheduled: None,
+            started: ended,
+            ended,
+            exit_code: None,
+            status,
+            trigger,
+            attempt: 1,
+            output,
+            message,
+        })
+    }
+}
+
+fn parse_millis(value: &str) -> Result<DateTime<Utc>, String> {
+    value
+        .parse::<i64>()
+        .ok()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .ok_or_else(|| format!("invalid time {}", value))
+}
+
+// Lines that can't be read are left out, each with the reason why
+pub fn parse_history(file_content: String) -> (History, Vec<ParseError>) {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(file_content.as_bytes());
+    let (mut history, mut errors) = (vec![], vec![]);
+    for (index, record) in reader.records().enumerate() {
+        // Messages may span several lines
+        let position = match &record {
+            Ok(fields) => fields.position(),
+            Err(e) => e.position(),
+        };
+        let line = position.map_or(index + 1, |position| position.line() as usize);
+        let parsed = record
+            .map_err(|e| e.to_string())
+            .and_then(|fields| HistoryRecord::from_fields(&fields));
+        match parsed {
+            Ok(record) => history.push(record),
+            Err(message) => errors.push(ParseError { line, message }),
+        }
+    }
+    (history, errors)
+}
+
+// The line is written at once so concurrent runs don't mix their records
+pub fn append_history(history_file: &Path, record: &HistoryRecord) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(record.fields())?;
+    let line = writer.into_inner().map_err(|e| e.into_error())?;
+    let _lock = lock_history(history_file)?;
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(history_file)?
+        .write_all(&line)
+}
+
+// Held by appends and by the rewrites of the CSV history, so a run ending while retention goes
+// through the file isn't lost. The lock is a file of its own, rewrites move a new history file
+// over the old one.
+pub(crate) fn lock_history(history_file: &Path) -> io::Result<File> {
+    let mut path = history_file.as_os_str().to_owned();
+    path.push(".lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(PathBuf::from(path))?;
+    lock.lock()?;
+    Ok(lock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_history_lines() {
+        let (history, errors) = parse_history(
+            "a,2024-05-01 02:00:00.250 UTC,Ok,\nb,1714528800000,ERROR,oops,\n\
+             c,2024-05-01 02:01:00 UTC,Error,exit 1, retrying\nd,2024-05-01 02:02:00 UTC,SUCCESS,\n"
+                .to_string(),
+        );
+        let read: Vec<(&str, RunStatus, &str)> = history
+            .iter()
+            .map(|record| (record.name.as_str(), record.status, record.message.as_str()))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                ("a", RunStatus::Success, ""),
+                ("b", RunStatus::Error, "oops"),
+                ("c", RunStatus::Error, "exit 1, retrying")
+            ]
+        );
+        assert_eq!(
+            history[0].ended,
+            Utc.timestamp_millis_opt(1714528800250).unwrap()
+        );
+        assert_eq!(history[0].run_id, "20240501-020000-250");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 4);
+    }
+    #[test]
+    fn history_records() {
+        let record = HistoryRecord {
+            run_id: "20240501-020000-000-1".to_string(),
+            name: "backup".to_string(),
+            scheduled: Some(Utc.timestamp_millis_opt(1714528800000).unwrap()),
+            started: Utc.timestamp_millis_opt(1714528800012).unwrap(),
+            ended: Utc.timestamp_millis_opt(1714528805000).unwrap(),
+            exit_code: Some(1),
+            status: RunStatus::Error,
+            trigger: Trigger::Schedule,
+            attempt: 1,
+            output: None,
+            message: "disk full, \"/\" at 100%\nretrying".to_string(),
+        };
+        let file = std::env::temp_dir().join(format!("history-{}", std::process::id()));
+        append_history(&file, &record).unwrap();
+        append_history(&file, &record).unwrap();
+        let content = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let (history, errors) = parse_history(content + "3,x\n");
+        assert_eq!(history, vec![record.clone(), record]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 5);
+    }
+}
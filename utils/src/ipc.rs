@@ -0,0 +1,324 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{prelude::*, BufReader};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+// Bumped on every change a client or daemon of the previous version couldn't handle
+pub const PROTOCOL_VERSION: u32 = 1;
+// Name of the control socket in ~/job_scheduler
+pub const SOCKET_NAME: &str = "daemon.sock";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// One JSON object per line on the control socket, each request gets exactly one response:
+// {"version":1,"command":"run_now","job":"backup"}
+// {"version":1,"result":"started","run_id":"20240501-020000-000"}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    ListJobs,
+    ListRuns,
+    // Start the job outside its schedule, `args` replace its arguments
+    RunNow {
+        job: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        args: Option<Vec<String>>,
+    },
+    Cancel {
+        run_id: String,
+    },
+    // Disable or enable a job, saved in the jobs file
+    Pause {
+        job: String,
+    },
+    Resume {
+        job: String,
+    },
+    // Stop or restart running every job, until the daemon restarts
+    PauseScheduler,
+    ResumeScheduler,
+    Reload,
+    Status,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    #[serde(flatten)]
+    pub reply: Reply,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Reply {
+    Jobs { jobs: Vec<JobState> },
+    Runs { runs: Vec<RunState> },
+    Started { run_id: String },
+    Status(Status),
+    Done,
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobState {
+    pub name: String,
+    pub cron: String,
+    pub next_run: DateTime<Utc>,
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunState {
+    pub run_id: String,
+    pub job: String,
+    pub started: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub pid: u32,
+    pub daemon_version: String,
+    pub started: DateTime<Utc>,
+    pub jobs: usize,
+    pub running: usize,
+    #[serde(default)]
+    pub paused: bool,
+}
+
+impl Request {
+    pub fn new(command: Command) -> Request {
+        Request {
+            version: PROTOCOL_VERSION,
+            command,
+        }
+    }
+}
+
+impl Response {
+    pub fn new(reply: Reply) -> Response {
+        Response {
+            version: PROTOCOL_VERSION,
+            reply,
+        }
+    }
+}
+
+// Send `command` to the daemon listening on `socket` and wait for its reply
+pub fn send(socket: &Path, command: Command) -> Result<Reply, String> {
+    let stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Couldn't reach the daemon at {}: {}", socket.display(), e))?;
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let mut line = serde_json::to_string(&Request::new(command)).map_err(|e| e.to_string())?;
+    line.push('\n');
+    (&stream)
+        .write_all(line.as_bytes())
+        .map_err(|e| format!("Couldn't send the request: {}", e))?;
+    let mut answer = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut answer)
+        .map_err(|e| format!("No answer from the daemon: {}", e))?;
+    let response: Response = serde_json::from_str(&answer)
+        .map_err(|e| format!("Unexpected answer from the daemon: {}", e))?;
+    if response.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "The daemon speaks version {} of the protocol, not {}",
+            response.version, PROTOCOL_VERSION
+        ));
+    }
+    Ok(response.reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    #[test]
+    fn control_protocol() {
+        let request: Request =
+            serde_json::from_str(r#"{"version":1,"command":"run_now","job":"backup"}"#).unwrap();
+        assert_eq!(
+            request.command,
+            Command::RunNow {
+                job: "backup".to_string(),
+                args: None
+            }
+        );
+        let response = Response::new(Reply::Started {
+            run_id: "20240501-020000-000".to_string(),
+        });
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"version":1,"result":"started","run_id":"20240501-020000-000"}"#
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"version":1,"command":"fly"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"command":"status"}"#).is_err());
+        // Read whatever the version, so the other side can say which one it speaks
+        let request: Request = serde_json::from_str(r#"{"version":2,"command":"status"}"#).unwrap();
+        assert_eq!(request.version, 2);
+    }
+    #[test]
+    fn round_trips() {
+        let time = DateTime::parse_from_rfc3339("2024-05-01T02:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let job = || "backup".to_string();
+        let commands = [
+            Command::ListJobs,
+            Command::ListRuns,
+            Command::RunNow {
+                job: job(),
+                args: Some(vec!["--full".to_string(), "a b".to_string()]),
+            },
+            Command::RunNow {
+                job: job(),
+                args: None,
+            },
+            Command::Cancel {
+                run_id: "20240501-020000-000".to_string(),
+            },
+            Command::Pause { job: job() },
+            Command::Resume { job: job() },
+            Command::PauseScheduler,
+            Command::ResumeScheduler,
+            Command::Reload,
+            Command::Status,
+        ];
+        for command in commands {
+            let line = serde_json::to_string(&Request::new(command.clone())).unwrap();
+            assert!(!line.contains('\n'));
+            let read: Request = serde_json::from_str(&line).unwrap();
+            assert_eq!(read, Request::new(command));
+        }
+        let replies = [
+            Reply::Jobs {
+                jobs: vec![JobState {
+                    name: job(),
+                    cron: "0 2 * * *".to_string(),
+                    next_run: time,
+                    paused: true,
+                }],
+            },
+            Reply::Runs {
+                runs: vec![RunState {
+                    run_id: "20240501-020000-000".to_string(),
+                    job: job(),
+                    started: time,
+                }],
+            },
+            Reply::Started {
+                run_id: "20240501-020000-000".to_string(),
+            },
+            Reply::Status(Status {
+                pid: 42,
+                daemon_version: "0.1.0".to_string(),
+                started: time,
+                jobs: 3,
+                running: 1,
+                paused: true,
+            }),
+            Reply::Done,
+            Reply::Error {
+                message: "No job named \"x\"\non this line".to_string(),
+            },
+        ];
+        for reply in replies {
+            let line = serde_json::to_string(&Response::new(reply.clone())).unwrap();
+            assert!(!line.contains('\n'));
+            let read: Response = serde_json::from_str(&line).unwrap();
+            assert_eq!(read, Response::new(reply));
+        }
+
+        assert_eq!(
+            serde_json::to_string(&Request::new(Command::Pause { job: job() })).unwrap(),
+            r#"{"version":1,"command":"pause","job":"backup"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Request::new(Command::PauseScheduler)).unwrap(),
+            r#"{"version":1,"command":"pause_scheduler"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Response::new(Reply::Done)).unwrap(),
+            r#"{"version":1,"result":"done"}"#
+        );
+        // Daemons from before the scheduler could be paused leave it out
+        let response: Response = serde_json::from_str(
+            r#"{"version":1,"result":"status","pid":42,"daemon_version":"0.1.0",
+                "started":"2024-05-01T02:00:00Z","jobs":3,"running":1}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            response.reply,
+            Reply::Status(Status { paused: false, .. })
+        ));
+    }
+    #[test]
+    fn sent_requests() {
+        let folder = std::env::temp_dir().join(format!("ipc-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let socket = folder.join(SOCKET_NAME);
+        assert!(send(&socket, Command::Status)
+            .unwrap_err()
+            .starts_with("Couldn't reach the daemon"));
+
+        // Answers each connection with the next line
+        let listener = UnixListener::bind(&socket).unwrap();
+        let daemon = thread::spawn(move || {
+            let answers = [
+                r#"{"version":1,"result":"started","run_id":"20240501-020000-000"}"#,
+                r#"{"version":2,"result":"done"}"#,
+                "not json",
+            ];
+            let mut requests = vec![];
+            for answer in answers {
+                let (stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                requests.push(request);
+                (&stream)
+                    .write_all(format!("{}\n", answer).as_bytes())
+                    .unwrap();
+            }
+            requests
+        });
+        let run = Command::RunNow {
+            job: "backup".to_string(),
+            args: None,
+        };
+        assert_eq!(
+            send(&socket, run),
+            Ok(Reply::Started {
+                run_id: "20240501-020000-000".to_string()
+            })
+        );
+        assert_eq!(
+            send(&socket, Command::Reload),
+            Err("The daemon speaks version 2 of the protocol, not 1".to_string())
+        );
+        assert!(send(&socket, Command::Status)
+            .unwrap_err()
+            .starts_with("Unexpected answer from the daemon"));
+        assert_eq!(
+            daemon.join().unwrap(),
+            [
+                "{\"version\":1,\"command\":\"run_now\",\"job\":\"backup\"}\n",
+                "{\"version\":1,\"command\":\"reload\"}\n",
+                "{\"version\":1,\"command\":\"status\"}\n",
+            ]
+        );
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
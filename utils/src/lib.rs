@@ -1,32 +1,73 @@
-use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use chrono::{DateTime, Datelike, Duration, LocalResult, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use core::fmt;
 use regex::Regex;
+use std::path::{Path, PathBuf};
 
+mod config;
+mod crontab;
+mod document;
+mod history;
+pub mod ipc;
+mod options;
 mod parser;
+mod retention;
+mod sources;
+mod stats;
+mod store;
+mod structured;
+
+pub use config::{default_config_file, Config, LogConfig, Notifications, CONFIG_FILE, LOG_LEVELS};
+pub use crontab::{export_crontab, import_crontab};
+pub use document::{update_jobs_file, JobsDocument};
+pub use history::{
+    append_history, parse_history, History, HistoryRecord, RunStatus, Trigger, HISTORY_VERSION,
+};
+pub use options::{
+    format_duration, format_env_line, format_size, parse_duration, parse_env_file, parse_env_line,
+    parse_size, parse_time_zone, CatchUp, Condition, JobOptions, Mode, DEFAULT_CATCH_UP_LIMIT,
+    DEFAULT_SHELL,
+};
+pub use retention::{compress_output, read_output, Expiry, Retention};
+pub use sources::{FileError, JobsFiles, JOBS_DIR};
+pub use stats::{job_stats, JobStats};
+pub use store::{
+    migrate_csv_history, CsvHistory, HistoryBackend, HistoryFilter, HistoryStore, SqliteHistory,
+    CSV_HISTORY_FILE, HISTORY_FILE,
+};
+pub use structured::JobsFormat;
 
 // const JOB_REGEX: &'static str = r"^([a-zA-Z_-]*)[[:blank:]]?:[[:blank:]]?(@(?:annually|yearly|monthly|weekly|daily|hourly|reboot)|(?:@every (?:\d+(?:ns|us|µs|ms|s|m|h))+)|(?:(?:(?:(?:\d+,)+\d+|(?:\d+(?:\\/|-)\d+)|\d+|\*) ?){5,7}))[[:blank:]](.*)$";
 const JOB_REGEX: &str = concat!(
-    r"^([a-zA-Z_-]*)[[:blank:]]*:[[:blank:]]*(", // name
+    r"^([a-zA-Z_-]*)[[:blank:]]*",                            // name
+    r#"(?:\[((?:[^\]"']|"[^"]*"|'[^']*')*)\][[:blank:]]*)?"#, // options
+    r":[[:blank:]]*(@manual|",
     r"(?:(?:(?:\d+,)+\d+|(?:(?:\d+|\*)(?:\/|-)(?:\d+|\*))|\d+|\*) ?){5}", // cron
-    ")[[:blank:]]+(.+)$"                         // command
+    ")[[:blank:]]+(.+)$"                                                  // command
 );
+const DEFAULTS_REGEX: &str = r#"^[[:blank:]]*\[((?:[^\]"']|"[^"]*"|'[^']*')*)\][[:blank:]]*$"#;
 
-const LOOP_LIMIT: u32 = 128;
+// Where a shell command reads its arguments: `$1`, `${2}`, `$@`, `$*`
+const ARGS_REGEX: &str = r"\$\{?[1-9@*]";
 
-pub type History = Vec<HistoryStatement>;
+const LOOP_LIMIT: u32 = 4096;
 
-#[derive(Debug)]
-pub struct HistoryStatement {
-    pub name: String,
-    pub timestamp: DateTime<Utc>,
-    pub status: String,
-    pub error_message: String,
-}
+// Schedule of the jobs only started by hand or by the jobs they come `after`
+pub const MANUAL: &str = "@manual";
 
 #[derive(Debug, Clone)]
 pub struct Job {
     pub name: String,
     pub cron: String,
     pub command: String,
+    pub options: JobOptions,
+    // A disabled job keeps its schedule but never runs, saved as `enabled=false`
+    pub enabled: bool,
+    // For the people reading the jobs file, the daemon doesn't use them
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    // File the job was read from, `None` for a job that wasn't read from a file yet
+    pub source: Option<PathBuf>,
     pub next_run: DateTime<Utc>,
 }
 
@@ -37,70 +78,383 @@ impl Job {
             next_run: get_next_run(&cron),
             cron,
             command,
+            options: JobOptions::default(),
+            enabled: true,
+            description: None,
+            tags: vec![],
+            source: None,
         }
     }
 }
 
 impl Job {
-    pub fn get_next_run(&mut self) {
-        self.next_run = get_next_run(&self.cron)
+    // Following the wall clock of the `tz` option
+    pub fn get_next_run(&mut self, defaults: &JobOptions) {
+        self.next_run = get_next_run_from_in(Utc::now(), &self.cron, self.time_zone(defaults))
+    }
+
+    // A scheduled job none of whose times can come, see `get_next_run_from_in`
+    pub fn never_runs(&self) -> bool {
+        self.cron != MANUAL && self.next_run == DateTime::<Utc>::MAX_UTC
+    }
+
+    pub fn time_zone(&self, defaults: &JobOptions) -> Tz {
+        self.options.tz.or(defaults.tz).unwrap_or(Tz::UTC)
+    }
+
+    // Program and arguments to run, with `defaults` filling the unset options
+    pub fn command_line(&self, defaults: &JobOptions) -> Vec<String> {
+        self.command_line_with(defaults, None)
+    }
+
+    // Whether arguments given to a run reach the command, shell commands must read them
+    pub fn takes_args(&self, defaults: &JobOptions) -> bool {
+        match self.options.inherit(defaults).mode.unwrap_or(Mode::Shell) {
+            Mode::Shell => Regex::new(ARGS_REGEX).unwrap().is_match(&self.command),
+            Mode::Exec => true,
+        }
+    }
+
+    // `args` replace the arguments of the command in exec mode. In shell mode, where the
+    // command has none of its own, they become the positional parameters `$1`, `$2`...
+    // No arguments leave the command as it is.
+    pub fn command_line_with(&self, defaults: &JobOptions, args: Option<&[String]>) -> Vec<String> {
+        let options = self.options.inherit(defaults);
+        let args = args.filter(|args| !args.is_empty());
+        match options.mode.unwrap_or(Mode::Shell) {
+            Mode::Shell => {
+                let mut command_line = vec![
+                    options.shell.unwrap_or(DEFAULT_SHELL.to_string()),
+                    "-c".to_string(),
+                    self.command.clone(),
+                ];
+                if let Some(args) = args {
+                    // Taken as `$0`
+                    command_line.push(self.name.clone());
+                    command_line.extend(args.iter().cloned());
+                }
+                command_line
+            }
+            Mode::Exec => {
+                // The command was already checked by `parse_job_line`
+                let mut command_line = shell_words::split(&self.command).unwrap_or_default();
+                if let Some(args) = args {
+                    command_line.truncate(1);
+                    command_line.extend(args.iter().cloned());
+                }
+                command_line
+            }
+        }
+    }
+
+    // What goes between the brackets after the name, empty when there is nothing to write
+    pub fn settings(&self) -> String {
+        let mut words = vec![];
+        if !self.enabled {
+            words.push("enabled=false".to_string());
+        }
+        if let Some(description) = &self.description {
+            words.push(format!("description={}", shell_words::quote(description)));
+        }
+        if !self.tags.is_empty() {
+            words.push(format!("tags={}", shell_words::quote(&self.tags.join(","))));
+        }
+        if !self.options.is_empty() {
+            words.push(self.options.to_string());
+        }
+        words.join(" ")
+    }
+
+    // Scheduled times to run now for the occurrences missed between `last_run` and `now`,
+    // following the `catch_up` option. Unset, the missed occurrences run once like anacron does.
+    pub fn missed_runs(
+        &self,
+        defaults: &JobOptions,
+        last_run: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Vec<DateTime<Utc>> {
+        let options = self.options.inherit(defaults);
+        let limit = match options.catch_up.unwrap_or(CatchUp::Once) {
+            CatchUp::Skip => return vec![],
+            CatchUp::Once => 1,
+            CatchUp::All => options.catch_up_limit.unwrap_or(DEFAULT_CATCH_UP_LIMIT) as usize,
+        };
+        missed_runs(&self.cron, last_run, now, limit, self.time_zone(defaults))
     }
 }
 
-pub fn parse_history(file_content: String) -> History {
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(file_content.as_bytes());
-    let mut history: History = vec![];
-    for record in reader.records().flatten() {
-        history.push(HistoryStatement {
-            name: record[0].to_owned(),
-            timestamp: Utc
-                .timestamp_millis_opt(record[1].parse::<i64>().unwrap())
-                .unwrap(),
-            status: record[2].to_owned(),
-            error_message: record[3].to_owned(),
-        })
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let settings = self.settings();
+        if settings.is_empty() {
+            write!(f, "{}: {} {}", self.name, self.cron, self.command)
+        } else {
+            write!(
+                f,
+                "{} [{}]: {} {}",
+                self.name, settings, self.cron, self.command
+            )
+        }
     }
-    history
 }
 
-pub fn parse_jobs(file_content: String) -> Vec<Job> {
-    let regex = Regex::new(JOB_REGEX).unwrap();
-    let mut jobs = vec![];
-    for line in file_content.lines() {
-        for (_, [name, cron, command]) in regex.captures_iter(line).map(|c| c.extract()) {
-            jobs.push(Job::new(
-                name.to_owned(),
-                cron.to_owned(),
-                command.to_owned(),
-            ));
+#[derive(Debug, Clone, Default)]
+pub struct JobsFile {
+    pub defaults: JobOptions,
+    pub jobs: Vec<Job>,
+    // Written back the way it was read
+    pub format: JobsFormat,
+}
+
+// In the format of the file
+impl fmt::Display for JobsFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.format != JobsFormat::Lines {
+            return write!(f, "{}", structured::to_string(self)?);
+        }
+        // Variables for every job are written back as crontab-like `KEY=value` lines
+        let mut lines: Vec<String> = self
+            .defaults
+            .env
+            .iter()
+            .map(|(name, value)| format_env_line(name, value))
+            .collect();
+        let defaults = JobOptions {
+            env: vec![],
+            ..self.defaults.clone()
+        };
+        if !defaults.is_empty() {
+            lines.push(format!("[{}]", defaults));
+        }
+        lines.extend(self.jobs.iter().map(|job| job.to_string()));
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse_jobs(file_content: String) -> Result<JobsFile, ParseError> {
+    let (jobs_file, job_lines) = read_lines(&file_content)?;
+    finish(jobs_file, &job_lines)
+}
+
+// Jobs file in the format its extension or content tells, see `JobsFormat::detect`
+pub fn parse_jobs_file(path: &Path, file_content: String) -> Result<JobsFile, ParseError> {
+    let (jobs_file, job_lines) = read_jobs(path, &file_content)?;
+    finish(jobs_file, &job_lines)
+}
+
+// Jobs of the file and the line of each, before the checks across jobs
+fn read_jobs(path: &Path, content: &str) -> Result<(JobsFile, Vec<usize>), ParseError> {
+    let (mut jobs_file, job_lines) = match JobsFormat::detect(path, content) {
+        JobsFormat::Lines => read_lines(content)?,
+        format => structured::read_jobs_as(content, format)?,
+    };
+    for job in &mut jobs_file.jobs {
+        job.source = Some(path.to_path_buf());
+    }
+    Ok((jobs_file, job_lines))
+}
+
+fn read_lines(content: &str) -> Result<(JobsFile, Vec<usize>), ParseError> {
+    let defaults_regex = Regex::new(DEFAULTS_REGEX).unwrap();
+    let mut jobs_file = JobsFile::default();
+    let mut job_lines = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let to_error = |message| ParseError {
+            line: index + 1,
+            message,
+        };
+        if let Some(caps) = defaults_regex.captures(line) {
+            let defaults = JobOptions::parse(&caps[1]).map_err(to_error)?;
+            jobs_file.defaults = defaults.inherit(&jobs_file.defaults);
+        } else if let Some((name, value)) = parse_env_line(line) {
+            options::set_env(&mut jobs_file.defaults.env, &name, &value);
+        } else if let Some(job) = parse_job_line(line).map_err(to_error)? {
+            jobs_file.jobs.push(job);
+            job_lines.push(index + 1);
         }
     }
-    jobs
+    Ok((jobs_file, job_lines))
 }
+
+// Checks across jobs, `job_lines` being where each job is in the file
+fn finish(mut jobs_file: JobsFile, job_lines: &[usize]) -> Result<JobsFile, ParseError> {
+    check_dependencies(&jobs_file).map_err(|(index, message)| ParseError {
+        line: job_lines[index],
+        message,
+    })?;
+    // The defaults may set the time zone after the jobs
+    for job in &mut jobs_file.jobs {
+        job.get_next_run(&jobs_file.defaults);
+    }
+    Ok(jobs_file)
+}
+
+// Every job named in `after` must exist, and no job may end up waiting for itself.
+// The error comes with the index of the job at fault.
+fn check_dependencies(jobs_file: &JobsFile) -> Result<(), (usize, String)> {
+    let jobs = &jobs_file.jobs;
+    let mut upstreams = vec![];
+    for (index, job) in jobs.iter().enumerate() {
+        let mut indexes = vec![];
+        for name in job.options.inherit(&jobs_file.defaults).after {
+            match jobs.iter().position(|job| job.name == name) {
+                Some(upstream) => indexes.push(upstream),
+                None => return Err((index, format!("`after` names an unknown job `{}`", name))),
+            }
+        }
+        upstreams.push(indexes);
+    }
+    // Depth-first walk, meeting a job that is still on the path closes a cycle
+    fn visit(
+        index: usize,
+        upstreams: &[Vec<usize>],
+        done: &mut [bool],
+        path: &mut Vec<usize>,
+    ) -> Result<(), Vec<usize>> {
+        if let Some(start) = path.iter().position(|&on_path| on_path == index) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(index);
+            return Err(cycle);
+        }
+        if done[index] {
+            return Ok(());
+        }
+        path.push(index);
+        for &upstream in &upstreams[index] {
+            visit(upstream, upstreams, done, path)?;
+        }
+        path.pop();
+        done[index] = true;
+        Ok(())
+    }
+    let mut done = vec![false; jobs.len()];
+    for index in 0..jobs.len() {
+        visit(index, &upstreams, &mut done, &mut vec![]).map_err(|cycle| {
+            let names: Vec<&str> = cycle.iter().rev().map(|&i| jobs[i].name.as_str()).collect();
+            (index, format!("dependency cycle {}", names.join(" -> ")))
+        })?;
+    }
+    Ok(())
+}
+
 pub fn parse_job(content: String) -> Option<Job> {
+    parse_job_line(&content).ok().flatten()
+}
+
+// `Ok(None)` when the line isn't a job at all, an error when it is one but can't be run
+fn parse_job_line(line: &str) -> Result<Option<Job>, String> {
     let regex = Regex::new(JOB_REGEX).unwrap();
-    regex.captures(&content).map(|caps| {
-        Job::new(
-            caps[1].to_string(),
-            caps[2].to_string(),
-            caps[3].to_string(),
-        )
-    })
+    let Some(caps) = regex.captures(line) else {
+        return Ok(None);
+    };
+    // Quoting is checked here for both modes so a typo doesn't wait for the next run to show up
+    match shell_words::split(&caps[4]) {
+        Ok(words) if words.is_empty() => return Err("empty command".to_string()),
+        Ok(_) => {}
+        Err(e) => return Err(format!("invalid command: {}", e)),
+    }
+    let mut job = Job::new(
+        caps[1].to_string(),
+        caps[3].to_string(),
+        caps[4].to_string(),
+    );
+    if let Some(settings) = caps.get(2) {
+        let words =
+            shell_words::split(settings.as_str()).map_err(|e| format!("invalid options: {}", e))?;
+        apply_settings(&mut job, words)?;
+    }
+    Ok(Some(job))
+}
+
+// `key=value` settings of a job. `enabled`, `description` and `tags` are taken out as they belong
+// to the job and not its options.
+fn apply_settings(job: &mut Job, words: Vec<String>) -> Result<(), String> {
+    let mut options = vec![];
+    for word in words {
+        match word.split_once('=').unwrap_or((&word, "")) {
+            ("enabled", value) => job.enabled = options::parse_bool("enabled", value)?,
+            ("description", "") => return Err("`description` needs a text".to_string()),
+            ("description", value) => job.description = Some(value.to_owned()),
+            ("tags", value) => {
+                job.tags = value.split(',').map(str::trim).map(str::to_owned).collect();
+                if job.tags.iter().any(|tag| tag.is_empty()) {
+                    return Err("`tags` needs names separated by commas".to_string());
+                }
+            }
+            _ => options.push(word),
+        }
+    }
+    job.options = JobOptions::parse(&shell_words::join(options))?;
+    Ok(())
 }
 
 pub fn get_next_run(cron: &str) -> DateTime<Utc> {
     get_next_run_from(Utc::now(), cron)
 }
 
+// First occurrences of `cron` after `since` and up to `until`, at most `limit` of them
+pub fn missed_runs(
+    cron: &str,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    limit: usize,
+    tz: Tz,
+) -> Vec<DateTime<Utc>> {
+    let mut runs = vec![];
+    let mut run = get_next_run_from_in(since, cron, tz);
+    while run <= until && runs.len() < limit {
+        runs.push(run);
+        run = get_next_run_from_in(run, cron, tz);
+    }
+    runs
+}
+
+// `cron` matched against the wall clock of `tz`. A time skipped when the clocks go forward
+// gets no run, one repeated when they go back runs once. `DateTime::<Utc>::MAX_UTC` when no time
+// can be found, which callers must tell apart from a `@manual` job.
+pub fn get_next_run_from_in(start: DateTime<Utc>, cron: &str, tz: Tz) -> DateTime<Utc> {
+    if cron == MANUAL {
+        return DateTime::<Utc>::MAX_UTC;
+    }
+    // Local times go through `get_next_run_from` as if they were UTC ones
+    let mut local = Utc.from_utc_datetime(&start.with_timezone(&tz).naive_local());
+    for _ in 0..LOOP_LIMIT {
+        local = get_next_run_from(local, cron);
+        match tz.from_local_datetime(&local.naive_utc()) {
+            LocalResult::Single(run) | LocalResult::Ambiguous(run, _) if run > start => {
+                return run.with_timezone(&Utc)
+            }
+            _ => {}
+        }
+    }
+    // Every time found was skipped by the clocks, the job gets no run like `@manual` ones
+    DateTime::<Utc>::MAX_UTC
+}
+
+// Never comes for `@manual` jobs
 pub fn get_next_run_from(start: DateTime<Utc>, cron: &str) -> DateTime<Utc> {
+    if cron == MANUAL {
+        return DateTime::<Utc>::MAX_UTC;
+    }
     let fields = parser::parse(cron);
-    let mut current_date = start
-        .clone()
-        .with_minute(start.minute() + 1)
-        .unwrap()
+    let mut current_date = (start + Duration::minutes(1))
         .with_second(0)
+        .unwrap()
+        .with_nanosecond(0)
         .unwrap();
     let mut step_count: u32 = 0;
     let days_in_month = vec![31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
@@ -112,13 +466,13 @@ pub fn get_next_run_from(start: DateTime<Utc>, cron: &str) -> DateTime<Utc> {
         let is_match_day_of_week =
             match_schedule((current_date.weekday() as u8) as u32, &fields[4]);
         let is_day_of_month_wildcard =
-            fields[2].len() >= days_in_month[current_date.month() as usize];
+            fields[2].len() >= days_in_month[current_date.month0() as usize];
         let is_day_of_week_wildcard = fields[4].len() == 7;
         if (!is_match_day_of_month && (!is_match_day_of_week || is_day_of_week_wildcard))
-            || (!is_day_of_month_wildcard && is_day_of_week_wildcard && !is_match_day_of_month)
             || (is_day_of_month_wildcard && !is_day_of_week_wildcard && !is_match_day_of_week)
         {
-            current_date += Duration::days(1);
+            let next_day = current_date.date_naive() + Duration::days(1);
+            current_date = Utc.from_utc_datetime(&next_day.and_hms_opt(0, 0, 0).unwrap());
             continue;
         }
 
@@ -138,7 +492,7 @@ pub fn get_next_run_from(start: DateTime<Utc>, cron: &str) -> DateTime<Utc> {
 
         // Match hour
         if !match_schedule(current_date.hour(), &fields[1]) {
-            current_date += Duration::hours(1);
+            current_date = (current_date + Duration::hours(1)).with_minute(0).unwrap();
             continue;
         }
 
@@ -152,7 +506,7 @@ pub fn get_next_run_from(start: DateTime<Utc>, cron: &str) -> DateTime<Utc> {
     if step_count > LOOP_LIMIT {
         todo!("error")
     }
-    return current_date;
+    current_date
 }
 
 fn match_schedule(value: u32, seq: &Vec<u32>) -> bool {
@@ -161,31 +515,261 @@ fn match_schedule(value: u32, seq: &Vec<u32>) -> bool {
             return el == &value;
         }
     }
-    return seq[0] == value;
+    seq[0] == value
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{TimeZone, Utc};
+    use chrono::{DateTime, TimeZone, Utc};
+    use chrono_tz::Tz;
 
-    use crate::get_next_run_from;
+    use crate::{
+        format_duration, get_next_run_from, get_next_run_from_in, missed_runs, parse_duration,
+        parse_env_file, parse_job, parse_jobs, CatchUp, Condition, JobOptions, Mode,
+    };
 
     #[test]
     fn simple_cron() {
-        let start = Utc.with_ymd_and_hms(2024, 05, 1, 0, 0, 0).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
         let date = get_next_run_from(start, "5 4 * * 6");
         assert_eq!(date.to_string(), "2024-05-05 04:05:00 UTC");
     }
     #[test]
     fn every_minutes() {
-        let start = Utc.with_ymd_and_hms(2024, 05, 1, 1, 1, 20).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 1, 1, 20).unwrap();
         let date = get_next_run_from(start, "* * * * *");
         assert_eq!(date.to_string(), "2024-05-01 01:02:00 UTC");
     }
     #[test]
     fn at_minutes_two() {
-        let start = Utc.with_ymd_and_hms(2024, 05, 1, 1, 0, 20).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 1, 0, 20).unwrap();
         let date = get_next_run_from(start, "2 * * * *");
         assert_eq!(date.to_string(), "2024-05-01 01:02:00 UTC");
     }
+    #[test]
+    fn end_of_year() {
+        let start = Utc.with_ymd_and_hms(2024, 12, 31, 23, 59, 30).unwrap();
+        let date = get_next_run_from(start, "30 2 * * *");
+        assert_eq!(date.to_string(), "2025-01-01 02:30:00 UTC");
+    }
+    #[test]
+    fn missed_occurrences() {
+        let since = Utc.with_ymd_and_hms(2024, 5, 1, 2, 0, 0).unwrap();
+        let until = Utc.with_ymd_and_hms(2024, 5, 4, 9, 0, 0).unwrap();
+        let runs = missed_runs("0 2 * * *", since, until, 10, Tz::UTC);
+        assert_eq!(runs.len(), 3);
+        assert_eq!(runs[0].to_string(), "2024-05-02 02:00:00 UTC");
+        assert_eq!(missed_runs("0 2 * * *", since, until, 2, Tz::UTC).len(), 2);
+
+        let job = parse_job("backup [catch_up=once]: 0 2 * * * backup".to_string()).unwrap();
+        assert_eq!(
+            job.missed_runs(&JobOptions::default(), since, until).len(),
+            1
+        );
+        let defaults = JobOptions {
+            catch_up: Some(CatchUp::All),
+            ..JobOptions::default()
+        };
+        let job = parse_job("backup: 0 2 * * * backup".to_string()).unwrap();
+        assert_eq!(job.missed_runs(&defaults, since, until).len(), 3);
+        assert_eq!(
+            job.missed_runs(&JobOptions::default(), since, until).len(),
+            1
+        );
+        let job = parse_job("backup [catch_up=skip]: 0 2 * * * backup".to_string()).unwrap();
+        assert!(job.missed_runs(&defaults, since, until).is_empty());
+    }
+    #[test]
+    fn time_zones() {
+        let paris: Tz = "Europe/Paris".parse().unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let date = get_next_run_from_in(start, "30 2 * * *", paris);
+        assert_eq!(date.to_string(), "2024-05-01 00:30:00 UTC");
+        // 02:30 doesn't exist on the day the clocks go forward
+        let start = Utc.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap();
+        let date = get_next_run_from_in(start, "30 2 * * *", paris);
+        assert_eq!(date.to_string(), "2024-04-01 00:30:00 UTC");
+        // 02:30 happens twice on the day they go back, it runs the first time only
+        let start = Utc.with_ymd_and_hms(2024, 10, 27, 0, 0, 0).unwrap();
+        let date = get_next_run_from_in(start, "30 2 * * *", paris);
+        assert_eq!(date.to_string(), "2024-10-27 00:30:00 UTC");
+        let date = get_next_run_from_in(date, "30 2 * * *", paris);
+        assert_eq!(date.to_string(), "2024-10-28 01:30:00 UTC");
+
+        let jobs_file = parse_jobs("a: 0 9 * * * a\n[tz=America/New_York]".to_string()).unwrap();
+        assert_eq!(jobs_file.defaults.to_string(), "tz=America/New_York");
+        let runs = jobs_file.jobs[0].missed_runs(
+            &JobOptions {
+                catch_up: Some(CatchUp::All),
+                ..jobs_file.defaults.clone()
+            },
+            start,
+            start + chrono::Duration::days(1),
+        );
+        assert_eq!(runs[0].to_string(), "2024-10-27 13:00:00 UTC");
+        assert!(parse_jobs("[tz=Mars/Olympus]".to_string()).is_err());
+    }
+    #[test]
+    fn disabled_job() {
+        let jobs_file = parse_jobs(
+            "a [enabled=false]: * * * * * ls\nb [mode=exec enabled=true]: * * * * * ls".to_string(),
+        )
+        .unwrap();
+        assert!(!jobs_file.jobs[0].enabled);
+        assert!(jobs_file.jobs[1].enabled);
+        assert_eq!(
+            jobs_file.to_string(),
+            "a [enabled=false]: * * * * * ls\nb [mode=exec]: * * * * * ls"
+        );
+        assert!(parse_jobs("[enabled=false]".to_string()).is_err());
+    }
+    #[test]
+    fn dependencies() {
+        let jobs_file = parse_jobs(
+            "a: 0 2 * * * extract\nc: 0 2 * * * fetch\nb [after=a,c on=always delay=5m]: @manual load"
+                .to_string(),
+        )
+        .unwrap();
+        let options = &jobs_file.jobs[2].options;
+        assert_eq!(options.after, vec!["a", "c"]);
+        assert_eq!(options.on, Some(Condition::Always));
+        assert_eq!(options.delay, Some(5 * 60));
+        assert_eq!(jobs_file.jobs[2].next_run, DateTime::<Utc>::MAX_UTC);
+        assert_eq!(
+            jobs_file.jobs[2].to_string(),
+            "b [after=a,c on=always delay=5m]: @manual load"
+        );
+
+        let error =
+            parse_jobs("a: * * * * * ls\nb [after=z]: * * * * * ls".to_string()).unwrap_err();
+        assert_eq!(error.line, 2);
+        let error = parse_jobs(
+            "a [after=c]: * * * * * ls\nb [after=a]: @manual ls\nc [after=b]: @manual ls"
+                .to_string(),
+        )
+        .unwrap_err();
+        assert_eq!(error.line, 1);
+        assert_eq!(error.message, "dependency cycle a -> b -> c -> a");
+    }
+    #[test]
+    fn shell_command_line() {
+        let job = parse_job("backup: 0 2 * * * tar czf /tmp/a.tgz ~ | wc -c".to_string()).unwrap();
+        assert_eq!(
+            job.command_line(&JobOptions::default()),
+            vec!["/bin/sh", "-c", "tar czf /tmp/a.tgz ~ | wc -c"]
+        );
+    }
+    #[test]
+    fn exec_command_line() {
+        let job = parse_job(r#"greet [mode=exec]: * * * * * echo "hello world" it\'s"#.to_string())
+            .unwrap();
+        assert_eq!(job.options.mode, Some(Mode::Exec));
+        assert_eq!(
+            job.command_line(&JobOptions::default()),
+            vec!["echo", "hello world", "it's"]
+        );
+    }
+    #[test]
+    fn overridden_arguments() {
+        let args = vec!["--dry-run".to_string(), "a b".to_string()];
+        let job = parse_job("sync [mode=exec]: * * * * * rsync -a /src /dst".to_string()).unwrap();
+        assert_eq!(
+            job.command_line_with(&JobOptions::default(), Some(&args)),
+            vec!["rsync", "--dry-run", "a b"]
+        );
+        let job = parse_job("greet: * * * * * echo \"$1\"".to_string()).unwrap();
+        assert_eq!(
+            job.command_line_with(&JobOptions::default(), Some(&args)),
+            vec!["/bin/sh", "-c", "echo \"$1\"", "greet", "--dry-run", "a b"]
+        );
+        assert!(job.takes_args(&JobOptions::default()));
+        assert_eq!(
+            job.command_line_with(&JobOptions::default(), Some(&[])),
+            vec!["/bin/sh", "-c", "echo \"$1\""]
+        );
+        let job = parse_job("backup: * * * * * backup.sh --full".to_string()).unwrap();
+        assert!(!job.takes_args(&JobOptions::default()));
+    }
+    #[test]
+    fn file_defaults() {
+        let jobs_file = parse_jobs(
+            "[mode=exec]\na: * * * * * ls\nb [shell=/bin/bash]: * * * * * ls".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            jobs_file.jobs[0].command_line(&jobs_file.defaults),
+            vec!["ls"]
+        );
+        assert_eq!(
+            jobs_file.jobs[1].command_line(&jobs_file.defaults),
+            vec!["/bin/bash", "-c", "ls"]
+        );
+    }
+    #[test]
+    fn malformed_quoting() {
+        let error =
+            parse_jobs("ok: * * * * * ls\nbad: * * * * * echo \"oops".to_string()).unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+    #[test]
+    fn global_env_lines() {
+        let jobs_file = parse_jobs(
+            "MAILTO=ops\nGREETING = \" hi \"\n[cwd=/srv env.MAILTO=dev]\na [env.A='x y']: * * * * * env"
+                .to_string(),
+        )
+        .unwrap();
+        let options = jobs_file.jobs[0].options.inherit(&jobs_file.defaults);
+        assert_eq!(options.cwd.as_deref(), Some("/srv"));
+        assert_eq!(
+            options.env,
+            vec![
+                ("MAILTO".to_string(), "dev".to_string()),
+                ("GREETING".to_string(), " hi ".to_string()),
+                ("A".to_string(), "x y".to_string())
+            ]
+        );
+        assert_eq!(
+            jobs_file.to_string(),
+            "MAILTO=dev\nGREETING=\" hi \"\n[cwd=/srv]\na [env.A='x y']: * * * * * env"
+        );
+    }
+    #[test]
+    fn env_file() {
+        let env = parse_env_file("# comment\n\nexport A=1\nB='2'\n").unwrap();
+        assert_eq!(
+            env,
+            vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "2".to_string())
+            ]
+        );
+        assert!(parse_env_file("A=1\nnot a variable").is_err());
+    }
+    #[test]
+    fn resource_limits() {
+        let job = parse_job(
+            "big [cpu_time=60 address_space=1536M memory_max=2G cpu_max=50%]: * * * * * make"
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(job.options.cpu_time, Some(60));
+        assert_eq!(job.options.address_space, Some(1536 << 20));
+        assert_eq!(job.options.memory_max, Some(2 << 30));
+        assert_eq!(job.options.cpu_max, Some(50));
+        assert_eq!(
+            job.to_string(),
+            "big [cpu_time=60 address_space=1536M memory_max=2G cpu_max=50%]: * * * * * make"
+        );
+        assert!(parse_job("big [memory_max=2X]: * * * * * make".to_string()).is_none());
+        assert!(JobOptions::parse("cpu_max=0%").is_err());
+    }
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("timeout", "90"), Ok(90));
+        assert_eq!(parse_duration("timeout", "90m"), Ok(90 * 60));
+        assert_eq!(parse_duration("timeout", "2d"), Ok(2 * 24 * 60 * 60));
+        assert!(parse_duration("timeout", "2w").is_err());
+        assert_eq!(format_duration(90), "90s");
+        assert_eq!(format_duration(2 * 60 * 60), "2h");
+    }
 }
@@ -0,0 +1,448 @@
+use chrono_tz::Tz;
+use regex::Regex;
+use std::fmt;
+
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+// Most missed runs replayed with `catch_up=all` when `catch_up_limit` isn't set
+pub const DEFAULT_CATCH_UP_LIMIT: u64 = 10;
+
+const ENV_REGEX: &str = r"^[[:blank:]]*(?:export[[:blank:]]+)?([A-Za-z_][A-Za-z0-9_]*)[[:blank:]]*=[[:blank:]]*(.*?)[[:blank:]]*$";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    // Hand the whole command to a shell with `-c`
+    Shell,
+    // Split the command into shell words and exec it directly
+    Exec,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mode::Shell => write!(f, "shell"),
+            Mode::Exec => write!(f, "exec"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CatchUp {
+    // Forget the runs missed while the daemon was down or the host asleep
+    Skip,
+    // Run once for all of them, the default
+    Once,
+    // Run every missed occurrence, up to `catch_up_limit`
+    All,
+}
+
+impl fmt::Display for CatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CatchUp::Skip => write!(f, "skip"),
+            CatchUp::Once => write!(f, "once"),
+            CatchUp::All => write!(f, "all"),
+        }
+    }
+}
+
+// Outcome of the jobs listed in `after` that starts a job
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Success,
+    Failure,
+    Always,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::Success => write!(f, "success"),
+            Condition::Failure => write!(f, "failure"),
+            Condition::Always => write!(f, "always"),
+        }
+    }
+}
+
+impl Condition {
+    pub fn is_met(&self, success: bool) -> bool {
+        match *self {
+            Condition::Success => success,
+            Condition::Failure => !success,
+            Condition::Always => true,
+        }
+    }
+}
+
+// Settings written between brackets after a job name: `name [mode=exec]: cron command`.
+// A bracketed line without any job sets the defaults for every job of the file.
+// Unset fields fall back to those defaults, then to the built-in ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobOptions {
+    pub mode: Option<Mode>,
+    pub shell: Option<String>,
+    // `env.KEY=value`, applied after `env_file` so they win over it
+    pub env: Vec<(String, String)>,
+    pub env_file: Option<String>,
+    pub cwd: Option<String>,
+    pub inherit_env: Option<bool>,
+    // Only honoured when the daemon runs as root
+    pub user: Option<String>,
+    pub group: Option<String>,
+    // Resource limits set with setrlimit in the job process, CPU time in seconds
+    pub cpu_time: Option<u64>,
+    pub address_space: Option<u64>,
+    pub open_files: Option<u64>,
+    pub processes: Option<u64>,
+    // cgroup v2 `memory.max` in bytes and `cpu.max` as a percentage of one CPU
+    pub memory_max: Option<u64>,
+    pub cpu_max: Option<u64>,
+    // Seconds before the job and everything it started are killed
+    pub timeout: Option<u64>,
+    // Runs started again right away after one that failed or timed out
+    pub retries: Option<u64>,
+    // What to do with the runs missed while the daemon couldn't start them, run once by default
+    pub catch_up: Option<CatchUp>,
+    pub catch_up_limit: Option<u64>,
+    // Jobs whose runs start this one once each of them ended as `on` asks, success by default.
+    // The run starts `delay` seconds after the last of them.
+    pub after: Vec<String>,
+    pub on: Option<Condition>,
+    pub delay: Option<u64>,
+    // Time zone whose wall clock the cron schedule follows, UTC by default
+    pub tz: Option<Tz>,
+}
+
+impl JobOptions {
+    pub fn parse(content: &str) -> Result<JobOptions, String> {
+        let words = shell_words::split(content).map_err(|e| format!("invalid options: {}", e))?;
+        let mut options = JobOptions::default();
+        for word in words {
+            let (key, value) = match word.split_once('=') {
+                Some((key, value)) => (key, value),
+                None => (word.as_str(), ""),
+            };
+            match key {
+                "mode" => {
+                    options.mode = Some(match value {
+                        "shell" => Mode::Shell,
+                        "exec" => Mode::Exec,
+                        _ => return Err(format!("unknown mode `{}`", value)),
+                    })
+                }
+                "shell" => options.shell = Some(parse_path(key, value)?),
+                "env_file" => options.env_file = Some(parse_path(key, value)?),
+                "cwd" => options.cwd = Some(parse_path(key, value)?),
+                "inherit_env" => options.inherit_env = Some(parse_bool(key, value)?),
+                "user" => options.user = Some(parse_name(key, value)?),
+                "group" => options.group = Some(parse_name(key, value)?),
+                "cpu_time" => options.cpu_time = Some(parse_number(key, value)?),
+                "address_space" => options.address_space = Some(parse_size(key, value)?),
+                "open_files" => options.open_files = Some(parse_number(key, value)?),
+                "processes" => options.processes = Some(parse_number(key, value)?),
+                "memory_max" => options.memory_max = Some(parse_size(key, value)?),
+                "timeout" => options.timeout = Some(parse_duration(key, value)?),
+                "retries" => options.retries = Some(parse_number(key, value)?),
+                "catch_up" => {
+                    options.catch_up = Some(match value {
+                        "skip" => CatchUp::Skip,
+                        "once" => CatchUp::Once,
+                        "all" => CatchUp::All,
+                        _ => return Err(format!("unknown catch_up policy `{}`", value)),
+                    })
+                }
+                "catch_up_limit" => options.catch_up_limit = Some(parse_number(key, value)?),
+                "after" => {
+                    options.after = value.split(',').map(str::trim).map(str::to_owned).collect();
+                    if options.after.iter().any(|name| name.is_empty()) {
+                        return Err("`after` needs job names separated by commas".to_string());
+                    }
+                }
+                "on" => {
+                    options.on = Some(match value {
+                        "success" => Condition::Success,
+                        "failure" => Condition::Failure,
+                        "always" => Condition::Always,
+                        _ => return Err(format!("unknown condition `{}`", value)),
+                    })
+                }
+                "delay" => options.delay = Some(parse_duration(key, value)?),
+                "tz" => options.tz = Some(parse_time_zone(key, value)?),
+                "cpu_max" => {
+                    let percent = value.strip_suffix('%').unwrap_or(value);
+                    // The kernel takes no less than 1% of a CPU
+                    options.cpu_max = match parse_number(key, percent)? {
+                        0 => return Err("`cpu_max` expects at least 1%".to_string()),
+                        percent => Some(percent),
+                    }
+                }
+                _ => match key.strip_prefix("env.") {
+                    Some(name) if is_env_name(name) => set_env(&mut options.env, name, value),
+                    Some(name) => return Err(format!("invalid variable name `{}`", name)),
+                    None => return Err(format!("unknown option `{}`", key)),
+                },
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == JobOptions::default()
+    }
+
+    // Fill every unset field from `defaults`, a job giving its own shell runs in shell mode
+    pub fn inherit(&self, defaults: &JobOptions) -> JobOptions {
+        let mode = match self.shell {
+            Some(_) => self.mode.or(Some(Mode::Shell)),
+            None => self.mode,
+        };
+        let mut env = defaults.env.clone();
+        for (name, value) in &self.env {
+            set_env(&mut env, name, value);
+        }
+        JobOptions {
+            mode: mode.or(defaults.mode),
+            shell: self.shell.clone().or_else(|| defaults.shell.clone()),
+            env,
+            env_file: self.env_file.clone().or_else(|| defaults.env_file.clone()),
+            cwd: self.cwd.clone().or_else(|| defaults.cwd.clone()),
+            inherit_env: self.inherit_env.or(defaults.inherit_env),
+            user: self.user.clone().or_else(|| defaults.user.clone()),
+            group: self.group.clone().or_else(|| defaults.group.clone()),
+            cpu_time: self.cpu_time.or(defaults.cpu_time),
+            address_space: self.address_space.or(defaults.address_space),
+            open_files: self.open_files.or(defaults.open_files),
+            processes: self.processes.or(defaults.processes),
+            memory_max: self.memory_max.or(defaults.memory_max),
+            cpu_max: self.cpu_max.or(defaults.cpu_max),
+            timeout: self.timeout.or(defaults.timeout),
+            retries: self.retries.or(defaults.retries),
+            catch_up: self.catch_up.or(defaults.catch_up),
+            catch_up_limit: self.catch_up_limit.or(defaults.catch_up_limit),
+            after: match self.after.is_empty() {
+                true => defaults.after.clone(),
+                false => self.after.clone(),
+            },
+            on: self.on.or(defaults.on),
+            delay: self.delay.or(defaults.delay),
+            tz: self.tz.or(defaults.tz),
+        }
+    }
+}
+
+impl fmt::Display for JobOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = vec![];
+        if let Some(mode) = self.mode {
+            words.push(format!("mode={}", mode));
+        }
+        if let Some(shell) = &self.shell {
+            words.push(format!("shell={}", shell_words::quote(shell)));
+        }
+        for (name, value) in &self.env {
+            words.push(format!("env.{}={}", name, shell_words::quote(value)));
+        }
+        if let Some(env_file) = &self.env_file {
+            words.push(format!("env_file={}", shell_words::quote(env_file)));
+        }
+        if let Some(cwd) = &self.cwd {
+            words.push(format!("cwd={}", shell_words::quote(cwd)));
+        }
+        if let Some(inherit_env) = self.inherit_env {
+            words.push(format!("inherit_env={}", inherit_env));
+        }
+        if let Some(user) = &self.user {
+            words.push(format!("user={}", shell_words::quote(user)));
+        }
+        if let Some(group) = &self.group {
+            words.push(format!("group={}", shell_words::quote(group)));
+        }
+        if let Some(cpu_time) = self.cpu_time {
+            words.push(format!("cpu_time={}", cpu_time));
+        }
+        if let Some(address_space) = self.address_space {
+            words.push(format!("address_space={}", format_size(address_space)));
+        }
+        if let Some(open_files) = self.open_files {
+            words.push(format!("open_files={}", open_files));
+        }
+        if let Some(processes) = self.processes {
+            words.push(format!("processes={}", processes));
+        }
+        if let Some(memory_max) = self.memory_max {
+            words.push(format!("memory_max={}", format_size(memory_max)));
+        }
+        if let Some(cpu_max) = self.cpu_max {
+            words.push(format!("cpu_max={}%", cpu_max));
+        }
+        if let Some(timeout) = self.timeout {
+            words.push(format!("timeout={}", format_duration(timeout)));
+        }
+        if let Some(retries) = self.retries {
+            words.push(format!("retries={}", retries));
+        }
+        if let Some(catch_up) = self.catch_up {
+            words.push(format!("catch_up={}", catch_up));
+        }
+        if let Some(catch_up_limit) = self.catch_up_limit {
+            words.push(format!("catch_up_limit={}", catch_up_limit));
+        }
+        if !self.after.is_empty() {
+            words.push(format!("after={}", self.after.join(",")));
+        }
+        if let Some(on) = self.on {
+            words.push(format!("on={}", on));
+        }
+        if let Some(delay) = self.delay {
+            words.push(format!("delay={}", format_duration(delay)));
+        }
+        if let Some(tz) = self.tz {
+            words.push(format!("tz={}", tz));
+        }
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+// Crontab-like `KEY=value` line, the value may be wrapped in matching quotes
+pub fn parse_env_line(line: &str) -> Option<(String, String)> {
+    let regex = Regex::new(ENV_REGEX).unwrap();
+    regex.captures(line).map(|caps| {
+        let value = &caps[2];
+        let unquoted = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) if value.len() >= 2 && value.ends_with(quote) => {
+                &value[1..value.len() - 1]
+            }
+            _ => value,
+        };
+        (caps[1].to_string(), unquoted.to_string())
+    })
+}
+
+// Content of an `env_file`: `KEY=value` lines, blank lines and `#` comments
+pub fn parse_env_file(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut env = vec![];
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match parse_env_line(line) {
+            Some((name, value)) => set_env(&mut env, &name, &value),
+            None => return Err(format!("line {}: expected KEY=value", index + 1)),
+        }
+    }
+    Ok(env)
+}
+
+pub fn format_env_line(name: &str, value: &str) -> String {
+    if value.trim() != value || value.starts_with(['"', '\'']) {
+        format!("{}=\"{}\"", name, value)
+    } else {
+        format!("{}={}", name, value)
+    }
+}
+
+pub(crate) fn set_env(env: &mut Vec<(String, String)>, name: &str, value: &str) {
+    match env.iter_mut().find(|(key, _)| key == name) {
+        Some(variable) => variable.1 = value.to_owned(),
+        None => env.push((name.to_owned(), value.to_owned())),
+    }
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_path(key: &str, value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err(format!("`{}` needs a path", key));
+    }
+    Ok(value.to_owned())
+}
+
+fn parse_name(key: &str, value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err(format!("`{}` needs a name", key));
+    }
+    Ok(value.to_owned())
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u64, String> {
+    value
+        .parse::<u64>()
+        .map_err(|_| format!("`{}` expects a number, not `{}`", key, value))
+}
+
+// Byte count with an optional binary K, M, G or T suffix
+pub fn parse_size(key: &str, value: &str) -> Result<u64, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "`{}` expects a size like 512M, not `{}`",
+                key, value
+            ))
+        }
+    };
+    parse_number(key, number)?
+        .checked_mul(multiplier)
+        .ok_or(format!("`{}` is too big", key))
+}
+
+pub fn format_size(size: u64) -> String {
+    for (unit, shift) in [("T", 40), ("G", 30), ("M", 20), ("K", 10)] {
+        if size != 0 && size.is_multiple_of(1 << shift) {
+            return format!("{}{}", size >> shift, unit);
+        }
+    }
+    size.to_string()
+}
+
+// Seconds with an optional s, m, h or d suffix
+pub fn parse_duration(key: &str, value: &str) -> Result<u64, String> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], 1),
+        Some((index, 'm')) => (&value[..index], 60),
+        Some((index, 'h')) => (&value[..index], 60 * 60),
+        Some((index, 'd')) => (&value[..index], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    parse_number(key, number)?
+        .checked_mul(multiplier)
+        .ok_or(format!("`{}` is too long", key))
+}
+
+// IANA name like Europe/Paris
+pub fn parse_time_zone(key: &str, value: &str) -> Result<Tz, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "`{}` expects a time zone like Europe/Paris, not `{}`",
+            key, value
+        )
+    })
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    for (unit, length) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)] {
+        if seconds != 0 && seconds.is_multiple_of(length) {
+            return format!("{}{}", seconds / length, unit);
+        }
+    }
+    format!("{}s", seconds)
+}
+
+pub(crate) fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "" | "true" | "yes" => Ok(true),
+        "false" | "no" => Ok(false),
+        _ => Err(format!("`{}` expects true or false, not `{}`", key, value)),
+    }
+}
@@ -45,7 +45,7 @@ fn parse_field(value: String, constraint: Constraint) -> Vec<u32> {
         .filter(|el| el < &constraint.min || el > &constraint.max)
         .collect();
     if outranged_numbers.is_empty() {
-        return stack;
+        stack
     } else {
         todo!("error")
     }
@@ -56,19 +56,17 @@ fn parse_sequence(val: String) -> Vec<u32> {
     for seq in val.split(",") {
         result.push(parse_repeat(seq.to_string()));
     }
-    return result.concat();
+    result.concat()
 }
 
 fn parse_repeat(val: String) -> Vec<u32> {
     let reps: Vec<&str> = val.split("/").collect();
     match reps.len() {
-        2 => {
-            return parse_range(
-                reps[0].to_string(),
-                reps[reps.len() - 1].parse::<u32>().unwrap(),
-            )
-        }
-        1 => return parse_range(val, 1),
+        2 => parse_range(
+            reps[0].to_string(),
+            reps[reps.len() - 1].parse::<u32>().unwrap(),
+        ),
+        1 => parse_range(val, 1),
         _ => todo!("Error, invalid cron expression"),
     }
 }
@@ -84,11 +82,11 @@ fn parse_range(val: String, repeat_interval: u32) -> Vec<u32> {
             }
 
             let mut stack: Vec<u32> = vec![];
-            let mut repeat_index = repeat_interval.clone();
+            let mut repeat_index = repeat_interval;
             for index in min..=max {
                 if !stack.contains(&index)
                     && repeat_index > 0
-                    && (repeat_index % repeat_interval) == 0
+                    && repeat_index.is_multiple_of(repeat_interval)
                 {
                     repeat_index = 1;
                     stack.push(index);
@@ -96,9 +94,9 @@ fn parse_range(val: String, repeat_interval: u32) -> Vec<u32> {
                     repeat_index += 1;
                 }
             }
-            return stack;
+            stack
         }
-        1 => return vec![val.parse::<u32>().unwrap()],
+        1 => vec![val.parse::<u32>().unwrap()],
         _ => todo!("Error, invalid cron expression"),
     }
 }
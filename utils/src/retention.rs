@@ -0,0 +1,191 @@
+use crate::HistoryRecord;
+use chrono::{DateTime, Duration, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+// How much history is kept, runs over any of the limits set are forgotten along with their output
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Retention {
+    // Seconds since the end of the run
+    pub max_age: Option<u64>,
+    // Runs kept for each job
+    pub max_runs: Option<usize>,
+    // Bytes taken by the outputs of all the runs, once compressed
+    pub max_size: Option<u64>,
+    // Seconds after which the output of a run is compressed, it's kept as is otherwise
+    pub compress_after: Option<u64>,
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        *self == Retention::default()
+    }
+
+    // Runs of `history`, most recent first, that are over the limits. `size` is the space taken
+    // by the output of a run, the most recent runs are the ones kept within `max_size`.
+    pub fn expired<'a>(
+        &self,
+        history: &'a [HistoryRecord],
+        now: DateTime<Utc>,
+        size: impl Fn(&HistoryRecord) -> u64,
+    ) -> Vec<&'a HistoryRecord> {
+        let mut expiry = self.expiry(now);
+        history
+            .iter()
+            .filter(|record| expiry.is_expired(record, size(record)))
+            .collect()
+    }
+
+    // Same as `expired`, for runs met one at a time
+    pub fn expiry(&self, now: DateTime<Utc>) -> Expiry<'_> {
+        Expiry {
+            retention: self,
+            now,
+            runs: HashMap::new(),
+            total_size: 0,
+        }
+    }
+
+    // Whether the output of `record` should be compressed by now
+    pub fn compresses(&self, record: &HistoryRecord, now: DateTime<Utc>) -> bool {
+        let compressed = record
+            .output
+            .as_ref()
+            .is_some_and(|output| is_compressed(output));
+        let old = self
+            .compress_after
+            .is_some_and(|after| now - record.ended > Duration::seconds(after as i64));
+        record.output.is_some() && !compressed && old
+    }
+}
+
+// What the runs kept so far take, the runs must come most recent first
+pub struct Expiry<'a> {
+    retention: &'a Retention,
+    now: DateTime<Utc>,
+    runs: HashMap<String, usize>,
+    total_size: u64,
+}
+
+impl Expiry<'_> {
+    // Whether `record`, whose output takes `size`, is over the limits
+    pub fn is_expired(&mut self, record: &HistoryRecord, size: u64) -> bool {
+        let retention = self.retention;
+        let too_old = retention
+            .max_age
+            .is_some_and(|age| self.now - record.ended > Duration::seconds(age as i64));
+        let count = self.runs.entry(record.name.clone()).or_default();
+        let too_many = retention
+            .max_runs
+            .is_some_and(|max_runs| *count >= max_runs);
+        let too_big = retention
+            .max_size
+            .is_some_and(|max_size| self.total_size + size > max_size);
+        let expired = too_old || too_many || too_big;
+        if !expired {
+            *count += 1;
+            self.total_size += size;
+        }
+        expired
+    }
+}
+
+fn is_compressed(output: &Path) -> bool {
+    output
+        .extension()
+        .is_some_and(|extension| extension == "gz")
+}
+
+// Replace the output with a gzip file next to it, whose path is returned
+pub fn compress_output(output: &Path) -> io::Result<PathBuf> {
+    let mut compressed_path = output.as_os_str().to_owned();
+    compressed_path.push(".gz");
+    let compressed_path = PathBuf::from(compressed_path);
+    let mut file = File::open(output)?;
+    let mut encoder = GzEncoder::new(File::create(&compressed_path)?, Compression::default());
+    io::copy(&mut file, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(output)?;
+    Ok(compressed_path)
+}
+
+// Content of an output, compressed or not
+pub fn read_output(output: &Path) -> io::Result<Vec<u8>> {
+    let mut content = vec![];
+    match is_compressed(output) {
+        true => GzDecoder::new(File::open(output)?).read_to_end(&mut content)?,
+        false => File::open(output)?.read_to_end(&mut content)?,
+    };
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_duration, RunStatus, Trigger};
+    use chrono::TimeZone;
+
+    #[test]
+    fn retention() {
+        let now = Utc.timestamp_millis_opt(1714528800000).unwrap();
+        let record = |name: &str, days: i64| HistoryRecord {
+            run_id: format!("{}-{}", name, days),
+            name: name.to_string(),
+            scheduled: None,
+            started: now - chrono::Duration::days(days),
+            ended: now - chrono::Duration::days(days),
+            exit_code: Some(0),
+            status: RunStatus::Success,
+            trigger: Trigger::Schedule,
+            attempt: 1,
+            output: Some(format!("/runs/{}.log", days).into()),
+            message: String::new(),
+        };
+        let history = vec![
+            record("a", 1),
+            record("b", 2),
+            record("a", 3),
+            record("a", 4),
+            record("b", 100),
+        ];
+        let run_ids = |retention: Retention| -> Vec<String> {
+            retention
+                .expired(&history, now, |_| 10)
+                .into_iter()
+                .map(|record| record.run_id.clone())
+                .collect()
+        };
+        assert!(run_ids(Retention::default()).is_empty());
+        let by_age = Retention {
+            max_age: Some(parse_duration("", "90d").unwrap()),
+            ..Retention::default()
+        };
+        assert_eq!(run_ids(by_age), ["b-100"]);
+        let by_count = Retention {
+            max_runs: Some(2),
+            ..Retention::default()
+        };
+        assert_eq!(run_ids(by_count), ["a-4"]);
+        let by_size = Retention {
+            max_size: Some(25),
+            ..Retention::default()
+        };
+        assert_eq!(run_ids(by_size), ["a-3", "a-4", "b-100"]);
+        let compressing = Retention {
+            compress_after: Some(parse_duration("", "3d").unwrap()),
+            ..Retention::default()
+        };
+        assert!(!compressing.compresses(&history[2], now));
+        assert!(compressing.compresses(&history[3], now));
+
+        let output = std::env::temp_dir().join(format!("output-{}.log", std::process::id()));
+        std::fs::write(&output, "some output\n".repeat(100)).unwrap();
+        let compressed = compress_output(&output).unwrap();
+        assert!(!output.exists() && compressed.to_string_lossy().ends_with(".log.gz"));
+        assert_eq!(read_output(&compressed).unwrap().len(), 1200);
+        std::fs::remove_file(compressed).unwrap();
+    }
+}
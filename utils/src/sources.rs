@@ -0,0 +1,244 @@
+use crate::{check_dependencies, read_jobs, JobOptions, JobsFile, JobsFormat};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Next to the main jobs file, ~/job_scheduler/jobs.d by default. Each of its files adds jobs of
+// its own, so packages and scripts can drop some in without touching the main file.
+pub const JOBS_DIR: &str = "jobs.d";
+
+// The main jobs file first, then the files of the jobs directory in name order. Each file keeps
+// its defaults and format, job names are unique across files and `after` may name a job of any
+// of them. A file that can't be used is left out, the others still load.
+#[derive(Debug, Clone, Default)]
+pub struct JobsFiles {
+    pub files: Vec<(PathBuf, JobsFile)>,
+}
+
+// Where a jobs file went wrong, no line when the file couldn't be read at all
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "{}: line {}: {}",
+                self.path.display(),
+                line,
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl JobsFiles {
+    // A missing main file or directory has no jobs. Hidden files and `~` backups in the
+    // directory are left out, editors leave them around. Comes with the errors of the files
+    // left out.
+    pub fn load(main: &Path, jobs_dir: &Path) -> (JobsFiles, Vec<FileError>) {
+        let read_error = |path: &Path, e: io::Error| FileError {
+            path: path.to_path_buf(),
+            line: None,
+            message: e.to_string(),
+        };
+        let mut errors = vec![];
+        let mut paths = match fs::read_dir(jobs_dir) {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<PathBuf>, io::Error>>()
+                .unwrap_or_else(|e| {
+                    errors.push(read_error(jobs_dir, e));
+                    vec![]
+                }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                errors.push(read_error(jobs_dir, e));
+                vec![]
+            }
+        };
+        paths.retain(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            path.is_file()
+                && name.is_some_and(|name| !name.starts_with('.') && !name.ends_with('~'))
+        });
+        paths.sort();
+        paths.insert(0, main.to_path_buf());
+        let mut contents = vec![];
+        for path in paths {
+            match fs::read_to_string(&path) {
+                Ok(content) => contents.push((path, content)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound && path == main => {
+                    contents.push((path, String::new()))
+                }
+                Err(e) => errors.push(read_error(&path, e)),
+            }
+        }
+        let (files, parse_errors) = JobsFiles::parse(contents);
+        errors.extend(parse_errors);
+        (files, errors)
+    }
+
+    // Files in the order their jobs come in. A file with a job named like one of the files
+    // before, or whose `after` can't be met without the files left out, is left out too.
+    pub fn parse(contents: Vec<(PathBuf, String)>) -> (JobsFiles, Vec<FileError>) {
+        let mut files = vec![];
+        let mut errors = vec![];
+        // Every job with the defaults of its file applied, and where it is
+        let mut all = JobsFile::default();
+        let mut places: Vec<(PathBuf, usize)> = vec![];
+        'files: for (path, content) in contents {
+            let (jobs_file, job_lines) = match read_jobs(&path, &content) {
+                Ok(read) => read,
+                Err(e) => {
+                    errors.push(FileError {
+                        path,
+                        line: Some(e.line),
+                        message: e.message,
+                    });
+                    continue;
+                }
+            };
+            for (job, line) in jobs_file.jobs.iter().zip(&job_lines) {
+                if let Some(index) = all.jobs.iter().position(|other| other.name == job.name) {
+                    let (other_path, other_line) = &places[index];
+                    errors.push(FileError {
+                        message: format!(
+                            "job `{}` is already in {} at line {}",
+                            job.name,
+                            other_path.display(),
+                            other_line
+                        ),
+                        path,
+                        line: Some(*line),
+                    });
+                    continue 'files;
+                }
+            }
+            for (job, line) in jobs_file.jobs.iter().zip(job_lines) {
+                let mut job = job.clone();
+                job.options = job.options.inherit(&jobs_file.defaults);
+                all.jobs.push(job);
+                places.push((path.clone(), line));
+            }
+            files.push((path, jobs_file));
+        }
+        // Leaving a file out may leave others waiting for a job that isn't there anymore
+        while let Err((index, message)) = check_dependencies(&all) {
+            let (path, line) = places[index].clone();
+            (all.jobs, places) = std::mem::take(&mut all.jobs)
+                .into_iter()
+                .zip(std::mem::take(&mut places))
+                .filter(|(_, (place, _))| *place != path)
+                .unzip();
+            files.retain(|(file, _)| *file != path);
+            errors.push(FileError {
+                path,
+                line: Some(line),
+                message,
+            });
+        }
+        for (_, jobs_file) in &mut files {
+            for job in &mut jobs_file.jobs {
+                job.get_next_run(&jobs_file.defaults);
+            }
+        }
+        (JobsFiles { files }, errors)
+    }
+
+    // Every job with the defaults of its file applied, `defaults` filling what the files leave
+    // unset
+    pub fn merge(&self, defaults: &JobOptions) -> JobsFile {
+        let jobs = self
+            .files
+            .iter()
+            .flat_map(|(_, jobs_file)| {
+                jobs_file.jobs.iter().map(|job| {
+                    let mut job = job.clone();
+                    job.options = job.options.inherit(&jobs_file.defaults);
+                    job.get_next_run(defaults);
+                    job
+                })
+            })
+            .collect();
+        JobsFile {
+            defaults: defaults.clone(),
+            jobs,
+            format: JobsFormat::default(),
+        }
+    }
+
+    // The file `name` was read from
+    pub fn file_of(&mut self, name: &str) -> Option<(&Path, &mut JobsFile)> {
+        self.files
+            .iter_mut()
+            .find(|(_, jobs_file)| jobs_file.jobs.iter().any(|job| job.name == name))
+            .map(|(path, jobs_file)| (path.as_path(), jobs_file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_directory() {
+        let main = PathBuf::from("/data/jobs");
+        let toml = PathBuf::from("/data/jobs.d/backup.toml");
+        let (files, errors) = JobsFiles::parse(vec![
+            (
+                main.clone(),
+                "[shell=/bin/bash]\na: 0 2 * * * extract".to_string(),
+            ),
+            (
+                toml.clone(),
+                "[defaults]\ntimeout = \"1h\"\n[[job]]\nname = \"b\"\ncron = \"@manual\"\n\
+                 command = \"load\"\nafter = [\"a\"]\n"
+                    .to_string(),
+            ),
+        ]);
+        assert!(errors.is_empty());
+        assert_eq!(files.files[1].1.format, JobsFormat::Toml);
+        assert_eq!(files.files[1].1.jobs[0].source.as_ref(), Some(&toml));
+        // Each job keeps the defaults of its own file
+        let merged = files.merge(&JobOptions::default());
+        assert_eq!(
+            merged.jobs[0].options.to_string(),
+            "mode=shell shell=/bin/bash"
+        );
+        assert_eq!(merged.jobs[1].options.to_string(), "timeout=1h after=a");
+
+        // Files that can't be used are left out, the others still load
+        let lines = PathBuf::from("/data/jobs.d/load");
+        let report = PathBuf::from("/data/jobs.d/report");
+        let (files, errors) = JobsFiles::parse(vec![
+            (main.clone(), "\na: 0 2 * * * extract".to_string()),
+            (lines.clone(), "d: @manual d\na: @manual load".to_string()),
+            (
+                PathBuf::from("/data/jobs.d/notify"),
+                "n [after=d]: @manual notify".to_string(),
+            ),
+            (report.clone(), "r [after=a]: @manual report".to_string()),
+        ]);
+        let paths: Vec<&PathBuf> = files.files.iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, vec![&main, &report]);
+        assert_eq!(
+            errors[0].to_string(),
+            "/data/jobs.d/load: line 2: job `a` is already in /data/jobs at line 2"
+        );
+        assert_eq!(errors[1].line, Some(1));
+        assert!(errors[1].message.contains("unknown job `d`"));
+        let (_, errors) = JobsFiles::parse(vec![(lines, "b [after=c]: @manual load".to_string())]);
+        assert_eq!(errors[0].line, Some(1));
+        assert!(errors[0].message.contains("unknown job `c`"));
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{HistoryRecord, RunStatus};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+// Aggregates over the runs of a job, `None` when no run tells
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobStats {
+    pub runs: usize,
+    // Between 0 and 1
+    pub success_rate: Option<f64>,
+    pub median_duration: Option<Duration>,
+    pub p95_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    // Failed runs since the last successful one
+    pub failure_streak: usize,
+    // From when the run was due to its start, over the scheduled runs only
+    pub average_delay: Option<Duration>,
+}
+
+impl JobStats {
+    // `runs` may come in any order, they are usually those of a single job
+    pub fn compute<'a>(runs: impl IntoIterator<Item = &'a HistoryRecord>) -> JobStats {
+        let mut runs: Vec<&HistoryRecord> = runs.into_iter().collect();
+        runs.sort_by_key(|run| run.started);
+        let succeeded = |run: &&&HistoryRecord| run.status == RunStatus::Success;
+        let successes = runs.iter().filter(succeeded).count();
+        let mut durations: Vec<Duration> = runs.iter().map(|run| run.ended - run.started).collect();
+        durations.sort();
+        let delays: Vec<Duration> = runs
+            .iter()
+            .filter_map(|run| run.scheduled.map(|scheduled| run.started - scheduled))
+            .collect();
+        JobStats {
+            runs: runs.len(),
+            success_rate: match runs.len() {
+                0 => None,
+                count => Some(successes as f64 / count as f64),
+            },
+            median_duration: percentile(&durations, 50),
+            p95_duration: percentile(&durations, 95),
+            max_duration: durations.last().copied(),
+            last_success: runs.iter().rev().find(succeeded).map(|run| run.started),
+            last_failure: runs
+                .iter()
+                .rev()
+                .find(|run| !succeeded(run))
+                .map(|run| run.started),
+            failure_streak: runs.iter().rev().take_while(|run| !succeeded(run)).count(),
+            average_delay: match delays.len() {
+                0 => None,
+                count => Some(
+                    delays
+                        .iter()
+                        .fold(Duration::zero(), |sum, delay| sum + *delay)
+                        / count as i32,
+                ),
+            },
+        }
+    }
+}
+
+// Nearest rank of sorted `values`
+fn percentile(values: &[Duration], percent: usize) -> Option<Duration> {
+    values
+        .get(percentile_index(values.len(), percent)?)
+        .copied()
+}
+
+// Where the nearest rank is among `count` sorted values
+pub(crate) fn percentile_index(count: usize, percent: usize) -> Option<usize> {
+    (count > 0).then(|| (count * percent).div_ceil(100).saturating_sub(1))
+}
+
+// Stats of every job found in `history`, by job name
+pub fn job_stats(history: &[HistoryRecord]) -> BTreeMap<String, JobStats> {
+    let mut runs: BTreeMap<String, Vec<&HistoryRecord>> = BTreeMap::new();
+    for record in history {
+        runs.entry(record.name.clone()).or_default().push(record);
+    }
+    runs.into_iter()
+        .map(|(name, runs)| (name, JobStats::compute(runs)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Trigger;
+    use chrono::TimeZone;
+
+    #[test]
+    fn run_stats() {
+        let start = Utc.timestamp_millis_opt(1714528800000).unwrap();
+        let record = |name: &str, minute: i64, seconds: i64, status: RunStatus| HistoryRecord {
+            run_id: format!("{}-{}", name, minute),
+            name: name.to_string(),
+            scheduled: Some(start + chrono::Duration::minutes(minute)),
+            started: start + chrono::Duration::minutes(minute) + chrono::Duration::seconds(2),
+            ended: start
+                + chrono::Duration::minutes(minute)
+                + chrono::Duration::seconds(2 + seconds),
+            exit_code: None,
+            status,
+            trigger: Trigger::Schedule,
+            attempt: 1,
+            output: None,
+            message: String::new(),
+        };
+        let mut history: Vec<HistoryRecord> = (1..=18)
+            .map(|minute| record("a", minute, minute, RunStatus::Success))
+            .collect();
+        history.push(record("a", 19, 100, RunStatus::Timeout));
+        history.push(record("a", 20, 1, RunStatus::Error));
+        history.push(record("b", 1, 1, RunStatus::Success));
+        let stats = job_stats(&history);
+        let a = &stats["a"];
+        assert_eq!(a.runs, 20);
+        assert_eq!(a.success_rate, Some(0.9));
+        assert_eq!(a.median_duration, Some(chrono::Duration::seconds(9)));
+        assert_eq!(a.p95_duration, Some(chrono::Duration::seconds(18)));
+        assert_eq!(a.max_duration, Some(chrono::Duration::seconds(100)));
+        assert_eq!(a.last_success, Some(history[17].started));
+        assert_eq!(a.last_failure, Some(history[19].started));
+        assert_eq!(a.failure_streak, 2);
+        assert_eq!(a.average_delay, Some(chrono::Duration::seconds(2)));
+        assert_eq!(stats["b"].failure_streak, 0);
+        assert_eq!(JobStats::compute([]), JobStats::default());
+    }
+}
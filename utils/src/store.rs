@@ -0,0 +1,748 @@
+use crate::history::lock_history;
+use crate::stats::percentile_index;
+use crate::{
+    append_history, parse_history, History, HistoryRecord, JobStats, RunStatus, Trigger,
+    HISTORY_VERSION,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::types::Type;
+use rusqlite::{params, params_from_iter, Connection, Row};
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// History database in the data folder, ~/job_scheduler by default
+pub const HISTORY_FILE: &str = "history.db";
+// Written before the database, moved into it once
+pub const CSV_HISTORY_FILE: &str = "history";
+
+// Time given to another connection to finish writing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        run_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        scheduled INTEGER,
+        started INTEGER NOT NULL,
+        ended INTEGER NOT NULL,
+        exit_code INTEGER,
+        status TEXT NOT NULL,
+        trigger TEXT NOT NULL,
+        attempt INTEGER NOT NULL,
+        output TEXT,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS runs_by_name ON runs (name, started);
+    CREATE INDEX IF NOT EXISTS runs_by_time ON runs (started);
+";
+
+// Runs matching every field that is set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryFilter {
+    pub name: Option<String>,
+    pub status: Option<RunStatus>,
+}
+
+pub trait HistoryStore {
+    fn append(&mut self, records: &[HistoryRecord]) -> Result<(), String>;
+    // Most recent first, after skipping `offset` of them
+    fn query(&self, filter: &HistoryFilter, offset: usize, limit: usize)
+        -> Result<History, String>;
+    fn count(&self, filter: &HistoryFilter) -> Result<usize, String>;
+    // `None` when the job never ran
+    fn last_started(&self, name: &str) -> Result<Option<DateTime<Utc>>, String>;
+    // Over every run of the job, without loading them
+    fn stats(&self, name: &str) -> Result<JobStats, String>;
+    // Every run, most recent first, handed over one at a time rather than all loaded at once
+    fn scan(&self, each: &mut dyn FnMut(HistoryRecord)) -> Result<(), String>;
+    fn remove(&mut self, records: &[HistoryRecord]) -> Result<(), String>;
+    // The output of the run moved to `output`
+    fn set_output(&mut self, record: &HistoryRecord, output: &Path) -> Result<(), String>;
+}
+
+// Where the runs are kept, set with `history.backend` in the config
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryBackend {
+    Sqlite,
+    Csv,
+}
+
+impl HistoryBackend {
+    pub fn open(&self, path: &Path) -> Result<Box<dyn HistoryStore + Send>, String> {
+        match self {
+            HistoryBackend::Sqlite => Ok(Box::new(SqliteHistory::open(path)?)),
+            HistoryBackend::Csv => Ok(Box::new(CsvHistory::open(path))),
+        }
+    }
+
+    // Default name in the data folder
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            HistoryBackend::Sqlite => HISTORY_FILE,
+            HistoryBackend::Csv => CSV_HISTORY_FILE,
+        }
+    }
+}
+
+pub struct SqliteHistory {
+    connection: Connection,
+}
+
+impl SqliteHistory {
+    // Created on first use. Both the daemon and the client open it, each write waits for the
+    // one in progress.
+    pub fn open(path: &Path) -> Result<SqliteHistory, String> {
+        let connection = Connection::open(path).map_err(|e| e.to_string())?;
+        connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .and_then(|_| {
+                // Readers don't wait for the writer, nor the other way around
+                connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))
+            })
+            .and_then(|_| connection.execute_batch(SCHEMA))
+            .and_then(|_| connection.pragma_update(None, "user_version", HISTORY_VERSION))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(SqliteHistory { connection })
+    }
+}
+
+impl HistoryStore for SqliteHistory {
+    fn append(&mut self, records: &[HistoryRecord]) -> Result<(), String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        {
+            let mut insert = transaction
+                .prepare_cached(
+                    "INSERT INTO runs (run_id, name, scheduled, started, ended, exit_code, status,
+                        trigger, attempt, output, message)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                )
+                .map_err(|e| e.to_string())?;
+            for record in records {
+                insert
+                    .execute(params![
+                        record.run_id,
+                        record.name,
+                        record.scheduled.map(|time| time.timestamp_millis()),
+                        record.started.timestamp_millis(),
+                        record.ended.timestamp_millis(),
+                        record.exit_code,
+                        record.status.to_string(),
+                        record.trigger.to_string(),
+                        record.attempt,
+                        record
+                            .output
+                            .as_ref()
+                            .map(|path| path.display().to_string()),
+                        record.message,
+                    ])
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+
+    fn query(
+        &self,
+        filter: &HistoryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<History, String> {
+        let (conditions, params) = filter.conditions();
+        let sql = format!(
+            "SELECT run_id, name, scheduled, started, ended, exit_code, status, trigger, attempt,
+                output, message
+            FROM runs {} ORDER BY started DESC, rowid DESC LIMIT {} OFFSET {}",
+            conditions, limit, offset
+        );
+        let mut select = self.connection.prepare(&sql).map_err(|e| e.to_string())?;
+        let rows = select
+            .query_map(params_from_iter(params), read_record)
+            .map_err(|e| e.to_string())?;
+        rows.map(|row| row.map_err(|e| e.to_string())).collect()
+    }
+
+    fn scan(&self, each: &mut dyn FnMut(HistoryRecord)) -> Result<(), String> {
+        let mut select = self
+            .connection
+            .prepare(
+                "SELECT run_id, name, scheduled, started, ended, exit_code, status, trigger,
+                    attempt, output, message
+                FROM runs ORDER BY started DESC, rowid DESC",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = select
+            .query_map([], read_record)
+            .map_err(|e| e.to_string())?;
+        for row in rows {
+            each(row.map_err(|e| e.to_string())?);
+        }
+        Ok(())
+    }
+
+    fn count(&self, filter: &HistoryFilter) -> Result<usize, String> {
+        let (conditions, params) = filter.conditions();
+        self.connection
+            .query_row(
+                &format!("SELECT COUNT(*) FROM runs {}", conditions),
+                params_from_iter(params),
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn last_started(&self, name: &str) -> Result<Option<DateTime<Utc>>, String> {
+        self.connection
+            .query_row(
+                "SELECT MAX(started) FROM runs WHERE name = ?1",
+                [name],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .map(|millis| millis.and_then(|millis| Utc.timestamp_millis_opt(millis).single()))
+            .map_err(|e| e.to_string())
+    }
+
+    fn stats(&self, name: &str) -> Result<JobStats, String> {
+        let time = |millis: Option<i64>| {
+            millis.and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        };
+        let (runs, successes, max_duration, last_success, last_failure, delays, delay_sum) = self
+            .connection
+            .query_row(
+                "SELECT COUNT(*), COUNT(CASE WHEN status = ?2 THEN 1 END), MAX(ended - started),
+                    MAX(CASE WHEN status = ?2 THEN started END),
+                    MAX(CASE WHEN status != ?2 THEN started END),
+                    COUNT(scheduled), SUM(started - scheduled)
+                FROM runs WHERE name = ?1",
+                params![name, RunStatus::Success.to_string()],
+                |row| {
+                    Ok((
+                        row.get::<_, usize>(0)?,
+                        row.get::<_, usize>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                        row.get::<_, Option<i64>>(3)?,
+                        row.get::<_, Option<i64>>(4)?,
+                        row.get::<_, i32>(5)?,
+                        row.get::<_, Option<i64>>(6)?,
+                    ))
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        let duration = |percent| match percentile_index(runs, percent) {
+            Some(index) => self
+                .connection
+                .query_row(
+                    "SELECT ended - started FROM runs WHERE name = ?1
+                    ORDER BY ended - started LIMIT 1 OFFSET ?2",
+                    params![name, index],
+                    |row| row.get(0),
+                )
+                .map(|millis| Some(chrono::Duration::milliseconds(millis)))
+                .map_err(|e| e.to_string()),
+            None => Ok(None),
+        };
+        // The runs since the last successful one, all of them without any
+        let failure_streak = self
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM runs WHERE name = ?1 AND started > ?2",
+                params![name, last_success.unwrap_or(i64::MIN)],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(JobStats {
+            runs,
+            success_rate: (runs > 0).then(|| successes as f64 / runs as f64),
+            median_duration: duration(50)?,
+            p95_duration: duration(95)?,
+            max_duration: max_duration.map(chrono::Duration::milliseconds),
+            last_success: time(last_success),
+            last_failure: time(last_failure),
+            failure_streak,
+            average_delay: delay_sum
+                .filter(|_| delays > 0)
+                .map(|sum| chrono::Duration::milliseconds(sum) / delays),
+        })
+    }
+
+    fn remove(&mut self, records: &[HistoryRecord]) -> Result<(), String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        {
+            let mut delete = transaction
+                .prepare_cached("DELETE FROM runs WHERE name = ?1 AND run_id = ?2")
+                .map_err(|e| e.to_string())?;
+            for record in records {
+                delete
+                    .execute([&record.name, &record.run_id])
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+
+    fn set_output(&mut self, record: &HistoryRecord, output: &Path) -> Result<(), String> {
+        self.connection
+            .execute(
+                "UPDATE runs SET output = ?1 WHERE name = ?2 AND run_id = ?3",
+                params![output.display().to_string(), record.name, record.run_id],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// The CSV history file, one line per run in the order they ended. Reads go through the whole
+// file, changes rewrite it with the lines that can't be read copied through as they are.
+pub struct CsvHistory {
+    path: PathBuf,
+}
+
+impl CsvHistory {
+    // Created on the first append
+    pub fn open(path: &Path) -> CsvHistory {
+        CsvHistory {
+            path: path.to_path_buf(),
+        }
+    }
+
+    fn read(&self) -> Result<History, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(parse_history(content).0),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(format!("{}: {}", self.path.display(), e)),
+        }
+    }
+
+    // Each run goes through `change`: left out on `None`, written again when it comes back
+    // changed, kept byte for byte otherwise. The new file is written next to the old one then
+    // moved over it, all under the lock appends take.
+    fn rewrite(
+        &self,
+        mut change: impl FnMut(&HistoryRecord) -> Option<HistoryRecord>,
+    ) -> Result<(), String> {
+        let error = |e: io::Error| format!("{}: {}", self.path.display(), e);
+        let _lock = lock_history(&self.path).map_err(error)?;
+        let content = match fs::read(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(error(e)),
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(content.as_slice());
+        let mut rewritten = vec![];
+        let mut fields = csv::ByteRecord::new();
+        let mut start = 0;
+        loop {
+            let read = reader.read_byte_record(&mut fields);
+            let end = reader.position().byte() as usize;
+            if matches!(read, Ok(false)) || end == start {
+                break;
+            }
+            let record = read
+                .ok()
+                .and_then(|_| csv::StringRecord::from_byte_record(fields.clone()).ok())
+                .and_then(|fields| HistoryRecord::from_fields(&fields).ok());
+            let line = match record {
+                None => content[start..end].to_vec(),
+                Some(record) => match change(&record) {
+                    None => vec![],
+                    Some(changed) if changed == record => content[start..end].to_vec(),
+                    Some(changed) => {
+                        let mut writer = csv::Writer::from_writer(vec![]);
+                        writer
+                            .write_record(changed.fields())
+                            .map_err(|e| e.to_string())?;
+                        writer.into_inner().map_err(|e| e.to_string())?
+                    }
+                },
+            };
+            if !line.is_empty() && !rewritten.is_empty() && !rewritten.ends_with(b"\n") {
+                rewritten.push(b'\n');
+            }
+            rewritten.extend(line);
+            start = end;
+        }
+        rewritten.extend(&content[start..]);
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, rewritten)
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .map_err(error)
+    }
+}
+
+impl HistoryStore for CsvHistory {
+    fn append(&mut self, records: &[HistoryRecord]) -> Result<(), String> {
+        for record in records {
+            append_history(&self.path, record)
+                .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    fn query(
+        &self,
+        filter: &HistoryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<History, String> {
+        let mut history: History = self
+            .read()?
+            .into_iter()
+            .rev()
+            .filter(|record| filter.matches(record))
+            .collect();
+        // Stable, runs started at the same time stay with the last written first
+        history.sort_by_key(|record| Reverse(record.started));
+        Ok(history.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn count(&self, filter: &HistoryFilter) -> Result<usize, String> {
+        Ok(self
+            .read()?
+            .iter()
+            .filter(|record| filter.matches(record))
+            .count())
+    }
+
+    fn last_started(&self, name: &str) -> Result<Option<DateTime<Utc>>, String> {
+        Ok(self
+            .read()?
+            .iter()
+            .filter(|record| record.name == name)
+            .map(|record| record.started)
+            .max())
+    }
+
+    fn scan(&self, each: &mut dyn FnMut(HistoryRecord)) -> Result<(), String> {
+        let history = self.query(&HistoryFilter::default(), 0, usize::MAX)?;
+        history.into_iter().for_each(each);
+        Ok(())
+    }
+
+    // The whole file is read anyway
+    fn stats(&self, name: &str) -> Result<JobStats, String> {
+        let history = self.read()?;
+        Ok(JobStats::compute(
+            history.iter().filter(|record| record.name == name),
+        ))
+    }
+
+    fn remove(&mut self, records: &[HistoryRecord]) -> Result<(), String> {
+        self.rewrite(|kept| {
+            let removed = records
+                .iter()
+                .any(|record| record.name == kept.name && record.run_id == kept.run_id);
+            (!removed).then(|| kept.clone())
+        })
+    }
+
+    fn set_output(&mut self, record: &HistoryRecord, output: &Path) -> Result<(), String> {
+        self.rewrite(|kept| {
+            let mut kept = kept.clone();
+            if kept.name == record.name && kept.run_id == record.run_id {
+                kept.output = Some(output.to_path_buf());
+            }
+            Some(kept)
+        })
+    }
+}
+
+impl HistoryFilter {
+    fn matches(&self, record: &HistoryRecord) -> bool {
+        self.name.as_ref().is_none_or(|name| *name == record.name)
+            && self.status.is_none_or(|status| status == record.status)
+    }
+
+    fn conditions(&self) -> (String, Vec<String>) {
+        let mut conditions = vec![];
+        let mut params = vec![];
+        if let Some(name) = &self.name {
+            params.push(name.clone());
+            conditions.push(format!("name = ?{}", params.len()));
+        }
+        if let Some(status) = self.status {
+            params.push(status.to_string());
+            conditions.push(format!("status = ?{}", params.len()));
+        }
+        match conditions.is_empty() {
+            true => (String::new(), params),
+            false => (format!("WHERE {}", conditions.join(" AND ")), params),
+        }
+    }
+}
+
+// A value the current version can't read fails the query rather than being left out
+fn invalid(index: usize, message: String) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(index, Type::Text, message.into())
+}
+
+fn read_time(row: &Row, index: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let millis: i64 = row.get(index)?;
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| invalid(index, format!("invalid time {}", millis)))
+}
+
+fn read_record(row: &Row) -> rusqlite::Result<HistoryRecord> {
+    let status: String = row.get(6)?;
+    let trigger: String = row.get(7)?;
+    Ok(HistoryRecord {
+        run_id: row.get(0)?,
+        name: row.get(1)?,
+        scheduled: match row.get::<_, Option<i64>>(2)? {
+            Some(_) => Some(read_time(row, 2)?),
+            None => None,
+        },
+        started: read_time(row, 3)?,
+        ended: read_time(row, 4)?,
+        exit_code: row.get(5)?,
+        status: RunStatus::parse(&status)
+            .ok_or_else(|| invalid(6, format!("invalid status {}", status)))?,
+        trigger: Trigger::parse(&trigger)
+            .ok_or_else(|| invalid(7, format!("invalid trigger {}", trigger)))?,
+        attempt: row.get(8)?,
+        output: row.get::<_, Option<String>>(9)?.map(PathBuf::from),
+        message: row.get(10)?,
+    })
+}
+
+// Move the runs of the CSV history into `store`, then rename the file so it's done only once.
+// Nothing is moved while some lines can't be read, the file stays to be fixed and tried again.
+pub fn migrate_csv_history(csv_file: &Path, store: &mut dyn HistoryStore) -> Result<usize, String> {
+    let content = fs::read_to_string(csv_file).map_err(|e| e.to_string())?;
+    let (history, errors) = parse_history(content);
+    if let Some(error) = errors.first() {
+        return Err(format!(
+            "{}: {}, {} unreadable lines in all",
+            csv_file.display(),
+            error,
+            errors.len()
+        ));
+    }
+    store.append(&history)?;
+    fs::rename(csv_file, csv_file.with_extension("migrated")).map_err(|e| e.to_string())?;
+    Ok(history.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs of a few jobs, some started at the same time, with commas, quotes and line breaks in
+    // their messages
+    fn runs() -> History {
+        let start = Utc.timestamp_millis_opt(1714528800000).unwrap();
+        let run = |name: &str, minute: i64, seconds: i64, status: RunStatus| HistoryRecord {
+            run_id: format!("{}-{}", name, minute),
+            name: name.to_string(),
+            scheduled: (minute % 3 != 0).then(|| {
+                start + chrono::Duration::minutes(minute) - chrono::Duration::milliseconds(1500)
+            }),
+            started: start + chrono::Duration::minutes(minute),
+            ended: start + chrono::Duration::minutes(minute) + chrono::Duration::seconds(seconds),
+            exit_code: (status != RunStatus::Killed)
+                .then_some((status != RunStatus::Success) as i32),
+            status,
+            trigger: match minute % 3 {
+                0 => Trigger::Manual,
+                _ => Trigger::Schedule,
+            },
+            attempt: 1,
+            output: Some(PathBuf::from(format!("/runs/{}-{}.log", name, minute))),
+            message: format!("{}, \"done\"\nafter {}s", name, seconds),
+        };
+        vec![
+            run("a", 1, 30, RunStatus::Success),
+            run("b", 1, 5, RunStatus::Error),
+            run("a", 2, 10, RunStatus::Error),
+            run("c", 3, 1, RunStatus::Timeout),
+            run("a", 3, 50, RunStatus::Success),
+            run("a", 4, 20, RunStatus::Success),
+            run("b", 5, 7, RunStatus::Killed),
+            run("a", 6, 40, RunStatus::Error),
+            run("a", 7, 15, RunStatus::Timeout),
+            run("b", 8, 9, RunStatus::Error),
+            run("d", 9, 3, RunStatus::Success),
+        ]
+    }
+
+    // Both backends holding `runs()`
+    fn stores(test: &str) -> (PathBuf, SqliteHistory, CsvHistory) {
+        let folder = std::env::temp_dir().join(format!("{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        let mut sqlite = SqliteHistory::open(&folder.join(HISTORY_FILE)).unwrap();
+        let mut csv = CsvHistory::open(&folder.join(CSV_HISTORY_FILE));
+        sqlite.append(&runs()).unwrap();
+        csv.append(&runs()).unwrap();
+        (folder, sqlite, csv)
+    }
+
+    // What a query should give: most recent first, the last written first among those started
+    // at the same time
+    fn newest_first(runs: &[HistoryRecord], filter: &HistoryFilter) -> History {
+        let mut matching: Vec<(usize, &HistoryRecord)> = runs
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| filter.matches(record))
+            .collect();
+        matching.sort_by_key(|(index, record)| Reverse((record.started, *index)));
+        matching
+            .into_iter()
+            .map(|(_, record)| record.clone())
+            .collect()
+    }
+
+    #[test]
+    fn csv_migration() {
+        let folder = std::env::temp_dir().join(format!("csv-migration-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let csv_file = folder.join(CSV_HISTORY_FILE);
+        let legacy_lines = "a,1714528800000,SUCCESS,\nb,1714528860000,ERROR,oops,\nbad line\n";
+        fs::write(&csv_file, legacy_lines).unwrap();
+        let mut store = SqliteHistory::open(&folder.join(HISTORY_FILE)).unwrap();
+        // Nothing moves while a line can't be read, the file is kept to try again
+        let error = migrate_csv_history(&csv_file, &mut store).unwrap_err();
+        assert!(error
+            .ends_with("line 3: expected at least 4 fields, found 1, 1 unreadable lines in all"));
+        assert!(csv_file.exists());
+        assert_eq!(store.count(&HistoryFilter::default()).unwrap(), 0);
+        // As the first daemon wrote it
+        let first_lines =
+            "a,2024-05-01 02:00:00.123456789 UTC,Ok,\nb,2024-05-01 02:01:00 UTC,Error,oops, again\n";
+        fs::write(&csv_file, first_lines).unwrap();
+        assert_eq!(migrate_csv_history(&csv_file, &mut store), Ok(2));
+        assert!(!csv_file.exists() && folder.join("history.migrated").exists());
+        let migrated = store.query(&HistoryFilter::default(), 0, 10).unwrap();
+        assert_eq!(migrated[0].message, "oops, again");
+        assert_eq!(migrated[1].status, RunStatus::Success);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+    #[test]
+    fn same_pages() {
+        let (folder, sqlite, csv) = stores("same-pages");
+        let runs = runs();
+        let filter = |name: Option<&str>, status: Option<RunStatus>| HistoryFilter {
+            name: name.map(str::to_string),
+            status,
+        };
+        let filters = [
+            HistoryFilter::default(),
+            filter(Some("a"), None),
+            filter(None, Some(RunStatus::Error)),
+            filter(Some("b"), Some(RunStatus::Killed)),
+            filter(Some("z"), None),
+        ];
+        for store in [&sqlite as &dyn HistoryStore, &csv] {
+            for filter in &filters {
+                let expected = newest_first(&runs, filter);
+                assert_eq!(store.count(filter).unwrap(), expected.len());
+                for (offset, limit) in [(0, 3), (3, 3), (2, 100), (100, 5)] {
+                    let page: History = expected.iter().skip(offset).take(limit).cloned().collect();
+                    assert_eq!(store.query(filter, offset, limit).unwrap(), page);
+                }
+            }
+            let mut scanned = vec![];
+            store.scan(&mut |record| scanned.push(record)).unwrap();
+            assert_eq!(scanned, newest_first(&runs, &HistoryFilter::default()));
+            assert_eq!(store.last_started("a").unwrap(), Some(runs[8].started));
+            assert_eq!(store.last_started("z").unwrap(), None);
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+    #[test]
+    fn same_stats() {
+        let (folder, sqlite, csv) = stores("same-stats");
+        let runs = runs();
+        for name in ["a", "b", "c", "d", "z"] {
+            let expected = JobStats::compute(runs.iter().filter(|record| record.name == name));
+            assert_eq!(sqlite.stats(name).unwrap(), expected, "{}", name);
+            assert_eq!(csv.stats(name).unwrap(), expected, "{}", name);
+        }
+        let a = sqlite.stats("a").unwrap();
+        assert_eq!(a.median_duration, Some(chrono::Duration::seconds(20)));
+        assert_eq!(a.p95_duration, Some(chrono::Duration::seconds(50)));
+        assert_eq!(a.failure_streak, 2);
+        let b = sqlite.stats("b").unwrap();
+        assert_eq!((b.failure_streak, b.last_success), (3, None));
+        assert_eq!(sqlite.stats("d").unwrap().average_delay, None);
+        assert_eq!(sqlite.stats("z").unwrap(), JobStats::default());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+    #[test]
+    fn same_changes() {
+        let (folder, mut sqlite, mut csv) = stores("same-changes");
+        let mut runs = runs();
+        let compressed = Path::new("/runs/a-1.log.gz");
+        for store in [&mut sqlite as &mut dyn HistoryStore, &mut csv] {
+            store.remove(&[runs[2].clone(), runs[6].clone()]).unwrap();
+            store.set_output(&runs[0], compressed).unwrap();
+            // Gone already
+            store.set_output(&runs[2], compressed).unwrap();
+        }
+        runs.remove(6);
+        runs.remove(2);
+        runs[0].output = Some(compressed.to_path_buf());
+        let expected = newest_first(&runs, &HistoryFilter::default());
+        for store in [&sqlite as &dyn HistoryStore, &csv] {
+            assert_eq!(
+                store.query(&HistoryFilter::default(), 0, 100).unwrap(),
+                expected
+            );
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+    #[test]
+    fn csv_rewrites() {
+        let folder = std::env::temp_dir().join(format!("csv-rewrites-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let file = folder.join(CSV_HISTORY_FILE);
+        let line = |record: &HistoryRecord| {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(record.fields()).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        let runs = runs();
+        let first_version = "a,1714528800000,SUCCESS,first\n";
+        let first_daemon = "b,2024-05-01 02:05:00 UTC,Error,exit 1, again\n";
+        // The last line has no line break
+        let content = [
+            first_version,
+            "not a run\n",
+            &line(&runs[0]),
+            first_daemon,
+            &line(&runs[1]),
+            "a,\"unfinished",
+        ]
+        .concat();
+        fs::write(&file, &content).unwrap();
+        let mut csv = CsvHistory::open(&file);
+        assert_eq!(csv.count(&HistoryFilter::default()).unwrap(), 4);
+        // Nothing changed, nothing is rewritten
+        csv.set_output(&runs[5], Path::new("/runs/none.log"))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), content);
+
+        csv.set_output(&runs[0], Path::new("/runs/a-1.log.gz"))
+            .unwrap();
+        csv.remove(&runs[1..2]).unwrap();
+        let mut moved = runs[0].clone();
+        moved.output = Some(PathBuf::from("/runs/a-1.log.gz"));
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            [
+                first_version,
+                "not a run\n",
+                &line(&moved),
+                first_daemon,
+                "a,\"unfinished"
+            ]
+            .concat()
+        );
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
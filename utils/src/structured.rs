@@ -0,0 +1,391 @@
+use crate::{apply_settings, parse_job_line, Job, JobOptions, JobsFile, ParseError};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+
+// Keys of a job table that aren't settings
+const JOB_KEYS: [&str; 3] = ["name", "cron", "command"];
+
+// How a jobs file is written
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum JobsFormat {
+    // `name [settings]: cron command` lines
+    #[default]
+    Lines,
+    // A `[defaults]` table and `[[job]]` tables with the same keys as the bracket settings
+    Toml,
+    // The TOML document written in YAML
+    Yaml,
+}
+
+impl JobsFormat {
+    // From the extension of `path`, or from the first line that isn't blank or a comment. A table
+    // header can't start a line file, its bracket settings never have brackets of their own.
+    pub fn detect(path: &Path, content: &str) -> JobsFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => return JobsFormat::Toml,
+            Some("yaml" | "yml") => return JobsFormat::Yaml,
+            _ => {}
+        }
+        let first_line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+        match first_line {
+            Some(line) if line.starts_with("[[") || line.starts_with("[defaults]") => {
+                JobsFormat::Toml
+            }
+            Some(line)
+                if line == "---" || line.starts_with("job:") || line.starts_with("defaults:") =>
+            {
+                JobsFormat::Yaml
+            }
+            _ => JobsFormat::Lines,
+        }
+    }
+}
+
+// Jobs of the document and the line of each
+pub(crate) fn read_jobs_as(
+    content: &str,
+    format: JobsFormat,
+) -> Result<(JobsFile, Vec<usize>), ParseError> {
+    let document = read_document(content, format)?;
+    let at_line = |line: usize| move |message: String| ParseError { line, message };
+    let mut jobs_file = JobsFile {
+        format,
+        ..JobsFile::default()
+    };
+    let mut job_lines = vec![];
+    let document = match document {
+        Value::Object(document) => document,
+        Value::Null => Map::new(),
+        _ => {
+            return Err(at_line(1)(
+                "expected `defaults` and `job` entries".to_string(),
+            ))
+        }
+    };
+    for (key, value) in document {
+        match (key.as_str(), value) {
+            ("defaults", Value::Object(defaults)) => {
+                let line = key_line(content, "defaults");
+                let words = settings_words(&defaults, &[]).map_err(at_line(line))?;
+                jobs_file.defaults =
+                    JobOptions::parse(&shell_words::join(words)).map_err(at_line(line))?;
+            }
+            ("job", Value::Array(jobs)) => {
+                for (index, job) in jobs.iter().enumerate() {
+                    let name = job.get("name").and_then(Value::as_str).unwrap_or_default();
+                    let line = job_line(content, name, index);
+                    let job = parse_job_table(job).map_err(|message| ParseError {
+                        line,
+                        message: match name.is_empty() {
+                            true => format!("job {}: {}", index + 1, message),
+                            false => format!("job `{}`: {}", name, message),
+                        },
+                    })?;
+                    jobs_file.jobs.push(job);
+                    job_lines.push(line);
+                }
+            }
+            ("defaults", _) => {
+                return Err(at_line(key_line(content, &key))(
+                    "`defaults` expects a table".to_string(),
+                ))
+            }
+            ("job", _) => {
+                return Err(at_line(key_line(content, &key))(
+                    "`job` expects a list of tables".to_string(),
+                ))
+            }
+            _ => {
+                return Err(at_line(key_line(content, &key))(format!(
+                    "unknown key `{}`, expected `defaults` or `job`",
+                    key
+                )))
+            }
+        }
+    }
+    Ok((jobs_file, job_lines))
+}
+
+fn read_document(content: &str, format: JobsFormat) -> Result<Value, ParseError> {
+    match format {
+        JobsFormat::Toml => toml::from_str(content).map_err(|e| ParseError {
+            line: e.span().map_or(1, |span| line_at(content, span.start)),
+            message: e.message().to_string(),
+        }),
+        #[cfg(feature = "yaml")]
+        JobsFormat::Yaml => serde_yaml::from_str(content).map_err(|e| ParseError {
+            line: e.location().map_or(1, |location| location.line()),
+            message: e.to_string(),
+        }),
+        #[cfg(not(feature = "yaml"))]
+        JobsFormat::Yaml => Err(ParseError {
+            line: 1,
+            message: "YAML jobs files need the `yaml` feature".to_string(),
+        }),
+        JobsFormat::Lines => unreachable!("line files are read by `read_lines`"),
+    }
+}
+
+// Name, schedule and command are checked like a job line, the other keys like its settings
+fn parse_job_table(table: &Value) -> Result<Job, String> {
+    let Value::Object(table) = table else {
+        return Err("expected a table".to_string());
+    };
+    let field = |key: &str| match table.get(key) {
+        Some(Value::String(value)) => Ok(value.as_str()),
+        Some(_) => Err(format!("`{}` expects a string", key)),
+        None => Err(format!("`{}` is missing", key)),
+    };
+    let (name, cron, command) = (field("name")?, field("cron")?, field("command")?);
+    if !Regex::new(r"^[a-zA-Z_-]+$").unwrap().is_match(name) {
+        return Err("`name` may only have letters, `_` and `-`".to_string());
+    }
+    if command.contains('\n') {
+        return Err("`command` must fit on one line".to_string());
+    }
+    let mut job = parse_job_line(&format!("{}: {} {}", name, cron, command))?.ok_or(format!(
+        "`cron` expects five fields or @manual, not `{}`",
+        cron
+    ))?;
+    apply_settings(&mut job, settings_words(table, &JOB_KEYS)?)?;
+    Ok(job)
+}
+
+// `key=value` words of the bracket settings, lists are joined with commas
+fn settings_words(table: &Map<String, Value>, skipped: &[&str]) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    for (key, value) in table {
+        if skipped.contains(&key.as_str()) {
+            continue;
+        }
+        match (key.as_str(), value) {
+            ("env", Value::Object(env)) => {
+                for (name, value) in env {
+                    let key = format!("env.{}", name);
+                    words.push(format!("{}={}", key, scalar(&key, value)?));
+                }
+            }
+            (_, Value::Array(items)) => {
+                let items: Result<Vec<String>, String> =
+                    items.iter().map(|item| scalar(key, item)).collect();
+                words.push(format!("{}={}", key, items?.join(",")));
+            }
+            _ => words.push(format!("{}={}", key, scalar(key, value)?)),
+        }
+    }
+    Ok(words)
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        _ => Err(format!("`{}` expects a string, a number or a boolean", key)),
+    }
+}
+
+// 1-based line of the byte at `offset`
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+// Where a top-level key is, for the errors about its value
+fn key_line(content: &str, key: &str) -> usize {
+    let regex = Regex::new(&format!(
+        r#"^\s*(?:\[{0}\]|{0}\s*[=:])"#,
+        regex::escape(key)
+    ))
+    .unwrap();
+    content
+        .lines()
+        .position(|line| regex.is_match(line))
+        .map_or(1, |index| index + 1)
+}
+
+// Where the job is: the line of its name, or the start of the `index`-th table
+fn job_line(content: &str, name: &str, index: usize) -> usize {
+    let name_regex = Regex::new(&format!(
+        r#"^\s*(?:-\s*)?name\s*[=:]\s*["']?{}["']?\s*(?:#.*)?$"#,
+        regex::escape(name)
+    ))
+    .unwrap();
+    let by_name = content.lines().position(|line| name_regex.is_match(line));
+    let by_table = || {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.trim_start().starts_with("[[job]]"))
+            .nth(index)
+            .map(|(position, _)| position)
+    };
+    by_name
+        .filter(|_| !name.is_empty())
+        .or_else(by_table)
+        .map_or(1, |position| position + 1)
+}
+
+pub(crate) fn to_string(jobs_file: &JobsFile) -> Result<String, fmt::Error> {
+    let mut document = Map::new();
+    if !jobs_file.defaults.is_empty() {
+        let defaults = settings_table(&jobs_file.defaults.to_string());
+        document.insert("defaults".to_string(), Value::Object(defaults));
+    }
+    let jobs = jobs_file
+        .jobs
+        .iter()
+        .map(|job| {
+            let mut table = Map::new();
+            table.insert("name".to_string(), Value::String(job.name.clone()));
+            table.insert("cron".to_string(), Value::String(job.cron.clone()));
+            table.insert("command".to_string(), Value::String(job.command.clone()));
+            table.extend(settings_table(&job.settings()));
+            Value::Object(table)
+        })
+        .collect();
+    document.insert("job".to_string(), Value::Array(jobs));
+    let document = Value::Object(document);
+    match jobs_file.format {
+        JobsFormat::Toml => toml::to_string(&document).map_err(|_| fmt::Error),
+        #[cfg(feature = "yaml")]
+        JobsFormat::Yaml => serde_yaml::to_string(&document).map_err(|_| fmt::Error),
+        #[cfg(not(feature = "yaml"))]
+        JobsFormat::Yaml => Err(fmt::Error),
+        JobsFormat::Lines => unreachable!("line files are written by `JobsFile`"),
+    }
+}
+
+// Bracket settings as table entries, with numbers and booleans given their own type
+fn settings_table(settings: &str) -> Map<String, Value> {
+    let mut table = Map::new();
+    let mut env = Map::new();
+    for word in shell_words::split(settings).unwrap_or_default() {
+        let (key, value) = word.split_once('=').unwrap_or((&word, ""));
+        let value = match key {
+            _ if key.starts_with("env.") => {
+                env.insert(
+                    key["env.".len()..].to_string(),
+                    Value::String(value.to_string()),
+                );
+                continue;
+            }
+            "after" | "tags" => Value::Array(
+                value
+                    .split(',')
+                    .map(|item| Value::String(item.to_string()))
+                    .collect(),
+            ),
+            "description" => Value::String(value.to_string()),
+            _ => match (value, value.parse::<u64>()) {
+                ("true", _) => Value::Bool(true),
+                ("false", _) => Value::Bool(false),
+                (_, Ok(number)) => Value::from(number),
+                _ => Value::String(value.to_string()),
+            },
+        };
+        table.insert(key.to_string(), value);
+    }
+    if !env.is_empty() {
+        table.insert("env".to_string(), Value::Object(env));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_jobs, parse_jobs_file};
+
+    #[test]
+    fn structured_jobs_files() {
+        let toml = r#"
+# Nightly jobs
+[defaults]
+timeout = "1h"
+env = { PATH = "/usr/bin" }
+
+[[job]]
+name = "backup"
+cron = "0 2 * * *"
+command = "backup.sh --all"
+description = "Copy the database"
+tags = ["db", "nightly"]
+retries = 2
+enabled = false
+
+[[job]]
+name = "report"
+cron = "@manual"
+command = "report.sh"
+after = ["backup"]
+"#;
+        let path = Path::new("jobs");
+        assert_eq!(JobsFormat::detect(path, toml), JobsFormat::Toml);
+        assert_eq!(
+            JobsFormat::detect(Path::new("jobs.yml"), ""),
+            JobsFormat::Yaml
+        );
+        assert_eq!(
+            JobsFormat::detect(path, "a: * * * * * ls"),
+            JobsFormat::Lines
+        );
+        let jobs_file = parse_jobs_file(path, toml.to_string()).unwrap();
+        assert_eq!(
+            jobs_file.defaults.to_string(),
+            "env.PATH=/usr/bin timeout=1h"
+        );
+        let backup = &jobs_file.jobs[0];
+        assert_eq!(backup.description.as_deref(), Some("Copy the database"));
+        assert_eq!(backup.tags, ["db", "nightly"]);
+        assert_eq!(backup.options.retries, Some(2));
+        assert!(!backup.enabled);
+        assert_eq!(jobs_file.jobs[1].options.after, ["backup"]);
+
+        // Written back in TOML, and the same jobs in the line format
+        let written = parse_jobs_file(path, jobs_file.to_string()).unwrap();
+        assert_eq!(written.format, JobsFormat::Toml);
+        assert_eq!(written.jobs[0].to_string(), backup.to_string());
+        assert_eq!(
+            backup.to_string(),
+            "backup [enabled=false description='Copy the database' tags=db,nightly retries=2]: \
+             0 2 * * * backup.sh --all"
+        );
+        let lines = JobsFile {
+            format: JobsFormat::Lines,
+            ..jobs_file.clone()
+        };
+        let reparsed = parse_jobs(lines.to_string()).unwrap();
+        assert_eq!(reparsed.jobs[0].tags, backup.tags);
+
+        let yaml = JobsFile {
+            format: JobsFormat::Yaml,
+            ..jobs_file
+        }
+        .to_string();
+        assert!(yaml.starts_with("defaults:"));
+        let from_yaml = parse_jobs_file(path, yaml).unwrap();
+        assert_eq!(
+            from_yaml.jobs[1].to_string(),
+            "report [after=backup]: @manual report.sh"
+        );
+
+        let error = |content: &str| parse_jobs_file(path, content.to_string()).unwrap_err();
+        let bad_timeout = "[[job]]\nname = \"a\"\ncron = \"* * * * *\"\ncommand = \"ls\"\n\
+            [[job]]\nname = \"b\"\ncron = \"* * * * *\"\ncommand = \"ls\"\ntimeout = \"x\"\n";
+        assert_eq!(error(bad_timeout).line, 6);
+        assert!(error(bad_timeout).message.starts_with("job `b`: `timeout`"));
+        assert_eq!(
+            error("[[job]]\nname = \"a\"\ncron = \"* *\"\ncommand = \"ls\"").line,
+            2
+        );
+        assert_eq!(error("[[job]]\nname = \"a\"\ncommand = ").line, 3);
+        assert!(error("[[jobs]]\nname = \"a\"")
+            .message
+            .contains("unknown key `jobs`"));
+    }
+}